@@ -1,10 +1,23 @@
-use std::{fs::{self, File}, io::Write};
+use std::time::Duration;
 
-use axum::{extract::Query, response::IntoResponse, routing::get, Extension};
-use megalodon::generator;
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{Html, IntoResponse},
+    routing::get,
+    Extension,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use megalodon::{generator, oauth::TokenData};
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tauri::Manager;
+use tokio::sync::oneshot;
 
+use crate::config;
+use crate::deep_link;
+use crate::error;
 use crate::state::{AppState, AuthState, ClientState};
 
 #[derive(Serialize)]
@@ -13,28 +26,20 @@ pub enum LoginState {
     LoggedOut,
 }
 
-pub fn save_state(state: &tauri::State<'_, AppState>) {
+pub fn save_state(state: &tauri::State<'_, AppState>) -> Result<(), error::DakkoError> {
     let config_dir = state.config_dir.read();
     let config_dir = config_dir.as_ref().unwrap();
-    fs::create_dir_all(&config_dir).unwrap();
 
-    {
-        let path = config_dir.join("auth.json");
-        let content =
-            serde_json::to_string_pretty(&state.auth_state.read().as_ref().unwrap().clone())
-                .unwrap();
-        let mut file = File::create(path).unwrap();
-        file.write_all(content.as_bytes()).unwrap();
-    }
-
-    {
-        let path = config_dir.join("client.json");
-        let content =
-            serde_json::to_string_pretty(&state.client_state.read().as_ref().unwrap().clone())
-                .unwrap();
-        let mut file = File::create(path).unwrap();
-        file.write_all(content.as_bytes()).unwrap();
-    }
+    config::save(
+        config_dir,
+        "auth.json",
+        state.auth_state.read().as_ref().unwrap(),
+    )?;
+    config::save(
+        config_dir,
+        "client.json",
+        state.client_state.read().as_ref().unwrap(),
+    )
 }
 
 #[tauri::command]
@@ -49,25 +54,155 @@ pub async fn login_state(state: tauri::State<'_, AppState>) -> Result<LoginState
 #[derive(Serialize)]
 pub struct AuthorizationURL(String);
 
+/// What a login asks for unless told otherwise
+pub const DEFAULT_SCOPES: [&str; 3] = ["read", "write", "follow"];
+
+/// How long the callback server waits for the instance to redirect back before giving up
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A login that has been started but not completed yet
+pub struct PendingLogin {
+    /// Sent as the `state` parameter, a callback without it did not come from our request
+    csrf: String,
+    redirect_uri: String,
+    /// The PKCE secret whose hash went out with the authorisation request
+    code_verifier: String,
+    scopes: Vec<String>,
+    /// Dropping this stops the callback server, out-of-band logins have none
+    _shutdown: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Serialize)]
+struct TokenRequest<'a> {
+    grant_type: &'static str,
+    client_id: &'a str,
+    client_secret: &'a str,
+    code: &'a str,
+    redirect_uri: &'a str,
+    code_verifier: &'a str,
+}
+
+pub fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), error::DakkoError> {
+    if scopes.is_empty() {
+        return Err(error::DakkoError::Invalid(
+            "at least one scope is needed".to_string(),
+        ));
+    }
+
+    // Servers keep adding scopes, like Mastodon's `profile`, so whether one exists is left to
+    // the server. We only catch what could never be a scope.
+    for scope in scopes {
+        let well_formed = !scope.is_empty()
+            && !scope.starts_with(':')
+            && !scope.ends_with(':')
+            && scope
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || matches!(c, ':' | '_' | '-' | '.'));
+
+        if !well_formed {
+            return Err(error::DakkoError::Invalid(format!("{} is not a scope", scope)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a granted scope covers a needed one. Broad scopes cover everything under them,
+/// so `write` covers `write:statuses`, and the legacy `follow` covers follows, blocks and
+/// mutes.
+fn covers(granted: &str, needed: &str) -> bool {
+    if granted == needed || needed.starts_with(&format!("{}:", granted)) {
+        return true;
+    }
+
+    granted == "follow"
+        && matches!(
+            needed,
+            "read:follows"
+                | "write:follows"
+                | "read:blocks"
+                | "write:blocks"
+                | "read:mutes"
+                | "write:mutes"
+        )
+}
+
+/// Fail up front when the token cannot do what a command needs, rather than letting the
+/// server answer with a bare 403
+pub fn require_scope(state: &AppState, scope: &str) -> Result<(), error::DakkoError> {
+    let auth_state = state.auth_state.read();
+    let granted = auth_state.as_ref().map_or(&[][..], |a| a.scopes.as_slice());
+
+    if granted.iter().any(|g| covers(g, scope)) {
+        Ok(())
+    } else {
+        Err(error::DakkoError::MissingScope(scope.to_string()))
+    }
+}
+
+/// The S256 PKCE challenge for a verifier
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Where the instance sends the user once they approve the login
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginMode {
+    /// Redirect to a server we run on localhost
+    Loopback,
+    /// Show the user a code to hand to `submit_auth_code`, for machines where local
+    /// listeners are blocked
+    OutOfBand,
+    /// Redirect to our own `dakko://` scheme
+    DeepLink,
+}
+
+impl Default for LoginMode {
+    fn default() -> Self {
+        Self::Loopback
+    }
+}
+
+/// Start logging in, asking for `scopes` or the defaults
 #[tauri::command]
 pub async fn login(
+    mode: Option<LoginMode>,
+    scopes: Option<Vec<String>>,
     state: tauri::State<'_, AppState>,
     handle: tauri::AppHandle,
-) -> Result<AuthorizationURL, ()> {
+) -> Result<AuthorizationURL, error::DakkoError> {
+    let mode = mode.unwrap_or_default();
+    let scopes = scopes.unwrap_or_else(|| DEFAULT_SCOPES.map(String::from).to_vec());
+    validate_scopes(&scopes)?;
+
+    let listener = if mode == LoginMode::Loopback {
+        // Bound before registering so the redirect uri carries the port we actually got
+        Some(tokio::net::TcpListener::bind(state.redirect_addr).await?)
+    } else {
+        None
+    };
+
+    let redirect_uri = match (&listener, mode) {
+        (Some(listener), _) => {
+            let local_addr = listener.local_addr()?;
+            format!("http://{}:{}/", local_addr.ip(), local_addr.port())
+        }
+        (None, LoginMode::DeepLink) => deep_link::OAUTH_REDIRECT.to_string(),
+        (None, _) => megalodon::default::NO_REDIRECT.to_string(),
+    };
+
     let options = megalodon::megalodon::AppInputOptions {
-        scopes: Some(
-            [
-                String::from("read"),
-                String::from("write"),
-                String::from("follow"),
-            ]
-            .to_vec(),
-        ),
-        redirect_uris: Some(format!(
-            "http://{}:{}/",
-            state.redirect_addr.ip(),
-            state.redirect_addr.port()
-        )),
+        scopes: Some(scopes.clone()),
+        redirect_uris: Some(redirect_uri.clone()),
         ..Default::default()
     };
 
@@ -77,33 +212,194 @@ pub async fn login(
     let mut client_state = state.client_state.write();
     let client_state = client_state.as_mut().unwrap();
 
-    match client.register_app(String::from("dakko"), &options).await {
-        Ok(app_data) => {
-            let client_id = app_data.client_id;
-            let client_secret = app_data.client_secret;
-            eprintln!("Authorization URL is generated {:#?}", app_data.url);
-            *client_state = ClientState {
-                client_id,
-                client_secret,
-                base_url: client_state.base_url.clone(),
-                instance_type: client_state.instance_type.clone()
-            };
-
-            tauri::async_runtime::spawn(async move { run_server(handle).await });
-
-            return Ok(AuthorizationURL(app_data.url.expect("url to be set")));
-        }
-        Err(err) => {
-            eprintln!("{:#?}", err);
-        }
+    let app_data = client
+        .register_app(String::from("dakko"), &options)
+        .await?;
+
+    let client_id = app_data.client_id;
+    let client_secret = app_data.client_secret;
+    eprintln!("Authorization URL is generated {:#?}", app_data.url);
+    *client_state = ClientState {
+        client_id,
+        client_secret,
+        base_url: client_state.base_url.clone(),
+        instance_type: client_state.instance_type.clone()
+    };
+
+    let url = app_data.url.ok_or_else(|| {
+        error::DakkoError::Invalid("instance did not return an authorisation url".to_string())
+    })?;
+    let mut url = reqwest::Url::parse(&url)
+        .map_err(|err| error::DakkoError::Invalid(format!("bad authorisation url: {}", err)))?;
+
+    let csrf = random_token(32);
+    let code_verifier = random_token(64);
+    url.query_pairs_mut()
+        .append_pair("state", &csrf)
+        .append_pair("code_challenge", &code_challenge(&code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    let shutdown = listener.map(|listener| {
+        let (shutdown, stopped) = oneshot::channel();
+        tauri::async_runtime::spawn(run_server(handle, listener, stopped, csrf.clone()));
+        shutdown
+    });
+
+    // Replacing an earlier attempt drops its shutdown handle, which stops its server
+    *state.pending_login.lock() = Some(PendingLogin {
+        csrf,
+        redirect_uri,
+        code_verifier,
+        scopes,
+        _shutdown: shutdown,
+    });
+
+    Ok(AuthorizationURL(url.to_string()))
+}
+
+/// Trade the authorisation code for a token and switch the client over to it
+async fn exchange_code(
+    state: &tauri::State<'_, AppState>,
+    code: &str,
+    pending: &PendingLogin,
+) -> Result<(), error::DakkoError> {
+    // Copied out so no lock is held while we wait on the instance
+    let client_state = state.client_state.read().clone().unwrap();
+
+    // megalodon has no way to send the PKCE verifier, so the exchange is done by hand
+    let body = TokenRequest {
+        grant_type: "authorization_code",
+        client_id: &client_state.client_id,
+        client_secret: &client_state.client_secret,
+        code: code.trim(),
+        redirect_uri: &pending.redirect_uri,
+        code_verifier: &pending.code_verifier,
+    };
+
+    let res = state
+        .http
+        .post(format!(
+            "{}/oauth/token",
+            client_state.base_url.trim_end_matches('/')
+        ))
+        .form(&body)
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        return Err(error::DakkoError::Status {
+            status: res.status().as_u16(),
+            message: "the instance refused the authorisation code".to_string(),
+        });
     }
 
-    Err(())
+    let token_data: TokenData = res.json().await?;
+
+    *state.client.write() = Some(generator(
+        megalodon::SNS::Pleroma,
+        client_state.base_url.clone(),
+        Some(token_data.access_token.clone()),
+        None,
+    ));
+    // The server may grant less than we asked for, and says so in the token response
+    let scopes = match token_data.scope.as_deref() {
+        Some(granted) if !granted.trim().is_empty() => {
+            granted.split_whitespace().map(String::from).collect()
+        }
+        _ => pending.scopes.clone(),
+    };
+
+    *state.auth_state.write() = Some(AuthState {
+        token: token_data,
+        scopes,
+    });
+    *state.server_filters.write() = None;
+    *state.domain_blocks.write() = None;
+    state.relationships.invalidate_all();
+    state.settings.reset_account();
+    state.quotes.reset();
+    state.capabilities.reset();
+    state.rate_limit.reset();
+
+    save_state(state)
 }
 
 #[derive(Deserialize)]
 pub struct CallbackQuery {
-    code: String,
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+fn callback_page(status: StatusCode, message: &str) -> (StatusCode, Html<String>) {
+    let message = message
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    let page = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Dakko</title></head>\
+         <body style=\"font-family: sans-serif; text-align: center; margin-top: 20vh\">\
+         <h1>{}</h1><p>{}</p></body></html>",
+        if status.is_success() {
+            "Logged in"
+        } else {
+            "Could not log in"
+        },
+        message
+    );
+
+    (status, Html(page))
+}
+
+/// Check a redirect against the pending login and finish it. `None` means it did not come
+/// from the login we started.
+async fn complete_callback(
+    state: &tauri::State<'_, AppState>,
+    query: &CallbackQuery,
+) -> Option<Result<(), error::DakkoError>> {
+    let pending = {
+        let mut pending = state.pending_login.lock();
+        let expected = pending.as_ref().map(|p| p.csrf.as_str());
+        if expected.is_none() || query.state.as_deref() != expected {
+            return None;
+        }
+
+        // Taking the pending login stops its server, if any, once we are done
+        pending.take().unwrap()
+    };
+
+    let result = match (&query.code, &query.error) {
+        (_, Some(error)) => Err(error::DakkoError::Invalid(
+            query.error_description.clone().unwrap_or_else(|| error.clone()),
+        )),
+        (Some(code), None) => exchange_code(state, code, &pending).await,
+        (None, None) => Err(error::DakkoError::Invalid(
+            "the instance did not send an authorisation code".to_string(),
+        )),
+    };
+
+    // Leave the login open, and its server running, so it can be tried again. Unless a newer
+    // login was started in the meantime.
+    if result.is_err() {
+        let mut current = state.pending_login.lock();
+        if current.is_none() {
+            *current = Some(pending);
+        }
+    }
+
+    Some(result)
+}
+
+fn report_login(handle: &tauri::AppHandle, result: &Result<(), error::DakkoError>) {
+    match result {
+        Ok(()) => handle.emit_all("auth-complete", ()).unwrap(),
+        Err(err) => {
+            eprintln!("Login failed: {}", err);
+            handle.emit_all("auth-failed", err.to_string()).unwrap();
+        }
+    }
 }
 
 pub async fn authorize(
@@ -112,61 +408,110 @@ pub async fn authorize(
 ) -> impl IntoResponse {
     let state = handle.state::<AppState>();
 
-    let mut client_lock = state.client.write();
-    let client = client_lock.as_mut().unwrap();
+    let result = match complete_callback(&state, &query).await {
+        Some(result) => result,
+        // Not ours, keep waiting for the real callback
+        None => {
+            return callback_page(
+                StatusCode::BAD_REQUEST,
+                "This link does not belong to the login Dakko is waiting for.",
+            )
+        }
+    };
 
-    let client_state_lock = state.client_state.read();
-    let client_state = client_state_lock.as_ref().unwrap();
+    report_login(&handle, &result);
+    match result {
+        Ok(()) => callback_page(StatusCode::OK, "You can close this window and return to Dakko."),
+        Err(err) => callback_page(StatusCode::BAD_REQUEST, &err.to_string()),
+    }
+}
 
-    let client_id = client_state.client_id.clone();
-    let client_secret = client_state.client_secret.clone();
+/// Finish a login the instance redirected to `dakko://oauth`
+pub async fn authorize_deep_link(handle: &tauri::AppHandle, url: &reqwest::Url) {
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.to_string())
+    };
 
-    let code = query.code.trim().to_string();
+    let query = CallbackQuery {
+        code: param("code"),
+        state: param("state"),
+        error: param("error"),
+        error_description: param("error_description"),
+    };
 
-    match client
-        .fetch_access_token(
-            client_id,
-            client_secret,
-            code,
-            megalodon::default::NO_REDIRECT.to_string(),
-        )
-        .await
-    {
-        Ok(token_data) => {
-            *client = generator(
-                megalodon::SNS::Pleroma,
-                client_state.base_url.clone(),
-                Some(token_data.access_token.clone()),
-                None,
-            );
-            *state.auth_state.write() = Some(AuthState { token: token_data });
-
-            // Drop our locks before save_state acquires them
-            drop(client_lock);
-            drop(client_state_lock);
-
-            save_state(&state);
-        }
-        Err(err) => {
-            println!("{:#?}", err);
+    let state = handle.state::<AppState>();
+    match complete_callback(&state, &query).await {
+        Some(result) => report_login(handle, &result),
+        None => eprintln!("Ignoring an OAuth callback for a login we did not start"),
+    }
+}
+
+/// Finish an out-of-band login with the code the instance showed the user
+#[tauri::command]
+pub async fn submit_auth_code(
+    code: String,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<(), error::DakkoError> {
+    let pending = state.pending_login.lock().take();
+    let pending = match pending {
+        Some(pending) if pending.redirect_uri == megalodon::default::NO_REDIRECT => pending,
+        other => {
+            // Put back a loopback login so its callback can still complete
+            *state.pending_login.lock() = other;
+            return Err(error::DakkoError::Invalid(
+                "no out-of-band login is in progress".to_string(),
+            ));
         }
+    };
+
+    if let Err(err) = exchange_code(&state, &code, &pending).await {
+        // Leave the login open so a mistyped code can be tried again
+        *state.pending_login.lock() = Some(pending);
+        return Err(err);
     }
 
     handle.emit_all("auth-complete", ()).unwrap();
 
-    "authorisation complete"
+    Ok(())
 }
 
-pub async fn run_server(handle: tauri::AppHandle) -> Result<(), axum::Error> {
+async fn run_server(
+    handle: tauri::AppHandle,
+    listener: tokio::net::TcpListener,
+    stopped: oneshot::Receiver<()>,
+    csrf: String,
+) {
     let app = axum::Router::new()
         .route("/", get(authorize))
         .layer(axum::Extension(handle.clone()));
 
-    let listener = tokio::net::TcpListener::bind(&handle.state::<AppState>().redirect_addr.clone())
-        .await
-        .unwrap();
+    let shutdown = async move {
+        tokio::select! {
+            _ = stopped => {}
+            _ = tokio::time::sleep(CALLBACK_TIMEOUT) => {
+                let state = handle.state::<AppState>();
+                let mut pending = state.pending_login.lock();
+
+                // A newer login may have taken over in the meantime
+                if pending.as_ref().map_or(false, |p| p.csrf == csrf) {
+                    *pending = None;
+                    drop(pending);
+                    handle
+                        .emit_all("auth-failed", "timed out waiting for the instance to redirect back")
+                        .unwrap();
+                }
+            }
+        }
+    };
 
     println!("Started server");
-    axum::serve(listener, app).await.unwrap();
-    Ok(())
+    if let Err(err) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        eprintln!("Callback server failed: {}", err);
+    }
 }
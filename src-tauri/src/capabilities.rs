@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::error;
+use crate::http::HttpClient;
+use crate::state::AppState;
+use crate::visibility::{self, Visibility};
+
+/// Posting is always possible in plain text, whatever the instance says
+const PLAIN_TEXT: &str = "text/plain";
+
+/// What the logged in instance can do beyond what every Mastodon API server can
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    /// Content types posts can be written in, from Pleroma's `post_formats`
+    pub post_formats: Vec<String>,
+    /// Visibilities posts can have, the Mastodon ones first
+    pub visibilities: Vec<Visibility>,
+}
+
+impl Capabilities {
+    fn from_instance(instance: &serde_json::Value) -> Self {
+        let mut post_formats: Vec<String> = instance["pleroma"]["metadata"]["post_formats"]
+            .as_array()
+            .map(|formats| {
+                formats
+                    .iter()
+                    .filter_map(|f| f.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !post_formats.iter().any(|f| f == PLAIN_TEXT) {
+            post_formats.insert(0, PLAIN_TEXT.to_string());
+        }
+
+        let mut visibilities: Vec<&str> = visibility::STANDARD.to_vec();
+
+        // Pleroma since 2.3 and Akkoma can keep posts to the instance
+        if instance["pleroma"].is_object() {
+            visibilities.push("local");
+        }
+
+        // Fedibird lists its extras as `visibility_mutual` and the like
+        if let Some(fedibird) = instance["fedibird_capabilities"].as_array() {
+            visibilities.extend(
+                fedibird
+                    .iter()
+                    .filter_map(|c| c.as_str()?.strip_prefix("visibility_")),
+            );
+        }
+
+        let visibilities = visibilities
+            .into_iter()
+            .filter_map(|name| Visibility::new(name).ok())
+            .fold(vec![], |mut all: Vec<Visibility>, v| {
+                if !all.contains(&v) {
+                    all.push(v);
+                }
+                all
+            });
+
+        Self {
+            post_formats,
+            visibilities,
+        }
+    }
+
+    /// Check a post's visibility is one the instance knows
+    pub fn check_visibility(&self, visibility: &Visibility) -> Result<(), error::DakkoError> {
+        if self.visibilities.contains(visibility) {
+            return Ok(());
+        }
+
+        Err(error::DakkoError::Invalid(format!(
+            "this instance has no {} visibility",
+            visibility.as_str()
+        )))
+    }
+
+    /// Check a post's content type is one the instance takes
+    pub fn check_format(&self, content_type: &str) -> Result<(), error::DakkoError> {
+        if self.post_formats.iter().any(|f| f == content_type) {
+            return Ok(());
+        }
+
+        Err(error::DakkoError::Invalid(format!(
+            "this instance can't take posts written in {}, it knows {}",
+            content_type,
+            self.post_formats.join(", ")
+        )))
+    }
+}
+
+/// What the logged in instance says about itself, looked up the first time it is needed
+#[derive(Default)]
+pub struct CapabilityCache {
+    instance: RwLock<Option<Arc<serde_json::Value>>>,
+}
+
+impl CapabilityCache {
+    /// Forget what we know, for when the instance changes
+    pub fn reset(&self) {
+        *self.instance.write() = None;
+    }
+}
+
+/// The instance's `/api/v1/instance`, shared by everything that needs to know what it
+/// supports so it is fetched once
+pub async fn instance(state: &AppState) -> Result<Arc<serde_json::Value>, error::DakkoError> {
+    if let Some(instance) = state.capabilities.instance.read().clone() {
+        return Ok(instance);
+    }
+
+    let instance = HttpClient::from_state(state)
+        .get::<serde_json::Value>("/api/v1/instance", &[])
+        .await?;
+    let instance = Arc::new(instance);
+
+    *state.capabilities.instance.write() = Some(instance.clone());
+    Ok(instance)
+}
+
+pub async fn get(state: &AppState) -> Result<Capabilities, error::DakkoError> {
+    Ok(Capabilities::from_instance(&instance(state).await?))
+}
+
+#[tauri::command]
+pub async fn get_capabilities(
+    state: tauri::State<'_, AppState>,
+) -> Result<Capabilities, error::DakkoError> {
+    assert!(state.has_logged_in());
+    get(&state).await
+}
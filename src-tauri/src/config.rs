@@ -0,0 +1,286 @@
+//! Versioned files in the config directory.
+//!
+//! Every file is written as `{ "version": n, "data": ... }`. Files from older versions are
+//! run through the migration chain when loaded and written back in the current format.
+//! Writes go to a temporary file which is renamed over the old one, so a crash mid-write
+//! leaves the previous version in place. A file we cannot read is moved aside rather than
+//! taking startup down with it.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::auth;
+use crate::error;
+use crate::state::AppState;
+
+pub const CURRENT_VERSION: u64 = 1;
+
+/// `MIGRATIONS[n]` turns the data of a version `n` file into version `n + 1`
+const MIGRATIONS: [fn(&str, Value) -> Value; 1] = [v0_to_v1];
+
+/// Version 0 files were the bare data, without an envelope. Logins from then predate
+/// configurable scopes and were always given the defaults.
+fn v0_to_v1(name: &str, mut data: Value) -> Value {
+    if name == "auth.json" {
+        if let Some(auth) = data.as_object_mut() {
+            auth.entry("scopes")
+                .or_insert_with(|| json!(auth::DEFAULT_SCOPES));
+        }
+    }
+
+    data
+}
+
+fn split_envelope(value: Value) -> (u64, Value) {
+    match value {
+        Value::Object(mut map) if map.contains_key("version") && map.contains_key("data") => {
+            let version = map.get("version").and_then(Value::as_u64).unwrap_or(0);
+            (version, map.remove("data").unwrap())
+        }
+        bare => (0, bare),
+    }
+}
+
+/// Bring data at `version` up to the current version
+fn migrate(name: &str, version: u64, mut data: Value) -> Value {
+    for migration in &MIGRATIONS[version as usize..] {
+        data = migration(name, data);
+    }
+
+    data
+}
+
+/// Move an unreadable file out of the way, returning where it went
+fn quarantine(path: &Path) -> Result<PathBuf, error::DakkoError> {
+    let stamp = chrono::Utc::now().format("%Y%m%d%H%M%S");
+    let moved_to = path.with_extension(format!("json.corrupt-{}", stamp));
+    fs::rename(path, &moved_to)?;
+    Ok(moved_to)
+}
+
+/// Load `name` from `dir`, migrating it if needed. A missing file is `Ok(None)`; a corrupt
+/// one is quarantined and reported as an error, after which loading again gives `Ok(None)`.
+pub fn load<T: DeserializeOwned + Serialize>(
+    dir: &Path,
+    name: &str,
+) -> Result<Option<T>, error::DakkoError> {
+    let path = dir.join(name);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let corrupt = |reason: String| -> error::DakkoError {
+        match quarantine(&path) {
+            Ok(moved_to) => error::DakkoError::Invalid(format!(
+                "{} could not be read ({}) and was moved to {}, the defaults are used instead",
+                name,
+                reason,
+                moved_to.display()
+            )),
+            Err(err) => err,
+        }
+    };
+
+    let value: Value = match serde_json::from_str(&content) {
+        Ok(value) => value,
+        Err(err) => return Err(corrupt(err.to_string())),
+    };
+
+    let (version, data) = split_envelope(value);
+    if version > CURRENT_VERSION {
+        // Leave it alone, a newer dakko can still use it
+        return Err(error::DakkoError::Invalid(format!(
+            "{} was written by a newer version of dakko (schema {}, this one knows {})",
+            name, version, CURRENT_VERSION
+        )));
+    }
+
+    let data = migrate(name, version, data);
+    let loaded: T = match serde_json::from_value(data) {
+        Ok(loaded) => loaded,
+        Err(err) => return Err(corrupt(err.to_string())),
+    };
+
+    if version < CURRENT_VERSION {
+        save(dir, name, &loaded)?;
+    }
+
+    Ok(Some(loaded))
+}
+
+/// Atomically write `value` to `name` in `dir` at the current version
+pub fn save<T: Serialize>(dir: &Path, name: &str, value: &T) -> Result<(), error::DakkoError> {
+    fs::create_dir_all(dir)?;
+
+    let content = serde_json::to_string_pretty(&json!({
+        "version": CURRENT_VERSION,
+        "data": value,
+    }))?;
+
+    let path = dir.join(name);
+    let tmp = dir.join(format!("{}.tmp", name));
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, &path)?;
+
+    Ok(())
+}
+
+/// Load a file through the app's config dir, keeping load errors for the frontend to show
+pub fn load_or_report<T: DeserializeOwned + Serialize>(state: &AppState, name: &str) -> Option<T> {
+    let config_dir = state.config_dir.read();
+    let config_dir = config_dir.as_ref().unwrap();
+
+    match load(config_dir, name) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("Could not load {}: {}", name, err);
+            state.config_errors.lock().push(err.to_string());
+            None
+        }
+    }
+}
+
+/// Problems found while loading the config dir, cleared once read
+#[tauri::command]
+pub async fn take_config_errors(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<String>, error::DakkoError> {
+    Ok(std::mem::take(&mut *state.config_errors.lock()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dakko-config-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn client() -> Value {
+        json!({
+            "client_id": "id",
+            "client_secret": "secret",
+            "base_url": "https://example.social",
+            "instance_type": "pleroma"
+        })
+    }
+
+    fn token() -> Value {
+        json!({
+            "access_token": "token",
+            "token_type": "Bearer",
+            "scope": "read write follow",
+            "created_at": 1700000000,
+            "expires_in": null,
+            "refresh_token": null
+        })
+    }
+
+    fn on_disk(dir: &Path, name: &str) -> Value {
+        serde_json::from_str(&fs::read_to_string(dir.join(name)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn v0_client_migrates_and_round_trips() {
+        let dir = scratch_dir("v0-client");
+        fs::write(dir.join("client.json"), client().to_string()).unwrap();
+
+        let loaded: Value = load(&dir, "client.json").unwrap().unwrap();
+        assert_eq!(loaded, client());
+
+        // Rewritten in the current format
+        assert_eq!(
+            on_disk(&dir, "client.json"),
+            json!({ "version": CURRENT_VERSION, "data": client() })
+        );
+
+        let reloaded: Value = load(&dir, "client.json").unwrap().unwrap();
+        assert_eq!(reloaded, loaded);
+    }
+
+    #[test]
+    fn v0_auth_gains_default_scopes() {
+        let dir = scratch_dir("v0-auth");
+        fs::write(
+            dir.join("auth.json"),
+            json!({ "token": token() }).to_string(),
+        )
+        .unwrap();
+
+        let loaded: Value = load(&dir, "auth.json").unwrap().unwrap();
+        assert_eq!(
+            loaded,
+            json!({ "token": token(), "scopes": ["read", "write", "follow"] })
+        );
+
+        let reloaded: Value = load(&dir, "auth.json").unwrap().unwrap();
+        assert_eq!(reloaded, loaded);
+    }
+
+    #[test]
+    fn v1_round_trips() {
+        let dir = scratch_dir("v1");
+        let auth = json!({ "token": token(), "scopes": ["read", "push"] });
+
+        save(&dir, "auth.json", &auth).unwrap();
+        save(&dir, "client.json", &client()).unwrap();
+
+        let loaded: Value = load(&dir, "auth.json").unwrap().unwrap();
+        assert_eq!(loaded, auth);
+        let loaded: Value = load(&dir, "client.json").unwrap().unwrap();
+        assert_eq!(loaded, client());
+
+        assert!(!dir.join("auth.json.tmp").exists());
+    }
+
+    #[test]
+    fn missing_file_is_none() {
+        let dir = scratch_dir("missing");
+        assert!(load::<Value>(&dir, "client.json").unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_file_is_quarantined() {
+        let dir = scratch_dir("corrupt");
+        fs::write(dir.join("client.json"), "{\"client_id\": ").unwrap();
+
+        assert!(load::<Value>(&dir, "client.json").is_err());
+        assert!(!dir.join("client.json").exists());
+
+        let quarantined = fs::read_dir(&dir).unwrap().filter_map(Result::ok).any(|e| {
+            e.file_name()
+                .to_string_lossy()
+                .starts_with("client.json.corrupt-")
+        });
+        assert!(quarantined);
+
+        // Startup can carry on as if nothing was there
+        assert!(load::<Value>(&dir, "client.json").unwrap().is_none());
+    }
+
+    #[test]
+    fn newer_version_is_left_alone() {
+        let dir = scratch_dir("newer");
+        let newer = json!({ "version": CURRENT_VERSION + 1, "data": client() });
+        fs::write(dir.join("client.json"), newer.to_string()).unwrap();
+
+        assert!(load::<Value>(&dir, "client.json").is_err());
+        assert_eq!(on_disk(&dir, "client.json"), newer);
+    }
+}
@@ -0,0 +1,202 @@
+use megalodon::entities;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::auth;
+use crate::error;
+use crate::request;
+use crate::state::AppState;
+use crate::user;
+
+/// Schemes we register as the system handler for
+pub const SCHEMES: [&str; 2] = ["dakko", "web+ap"];
+/// Redirect uri for logins that come back through the scheme handler
+pub const OAUTH_REDIRECT: &str = "dakko://oauth";
+
+/// What a link points at, before asking the instance about it
+enum DeepLink {
+    Profile(String),
+    Tag(String),
+    /// Anything else, looked up by its url
+    Object(String),
+}
+
+/// Where the frontend should go
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Navigation {
+    Profile { account: entities::Account },
+    Status { status: entities::Status },
+    Tag { name: String },
+}
+
+fn decode(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().to_string()
+}
+
+/// `dakko://` links mirror `web+ap://`, both are the https url with the scheme swapped
+fn classify(url: &reqwest::Url) -> Result<DeepLink, error::DakkoError> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| error::DakkoError::Invalid("link has no host".to_string()))?
+        .to_lowercase();
+
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    let profile = |user: &str| {
+        let user = decode(user.trim_start_matches('@'));
+        // Remote profiles viewed on another instance already carry their domain
+        if user.contains('@') {
+            user
+        } else {
+            format!("{}@{}", user, host)
+        }
+    };
+
+    Ok(match segments.as_slice() {
+        ["tags", tag] => DeepLink::Tag(decode(tag)),
+        [user] if user.starts_with('@') => DeepLink::Profile(profile(user)),
+        ["users", user] => DeepLink::Profile(profile(user)),
+        _ => {
+            let mut https = url.clone();
+            https
+                .set_scheme("https")
+                .map_err(|_| error::DakkoError::Invalid("link cannot be opened".to_string()))?;
+            DeepLink::Object(https.to_string())
+        }
+    })
+}
+
+async fn resolve(
+    state: tauri::State<'_, AppState>,
+    link: DeepLink,
+) -> Result<Navigation, error::DakkoError> {
+    let url = match link {
+        DeepLink::Tag(name) => return Ok(Navigation::Tag { name }),
+        DeepLink::Profile(acct) => {
+            let account = user::lookup_account(acct, state).await?;
+            return Ok(Navigation::Profile { account });
+        }
+        DeepLink::Object(url) => url,
+    };
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    // A resolving search fetches the object onto our instance if it has not seen it yet
+    let options = megalodon::megalodon::SearchInputOptions {
+        resolve: Some(true),
+        limit: Some(1),
+        ..Default::default()
+    };
+
+    let res = request::read(&state.rate_limit, || {
+        client.search(url.clone(), Some(&options))
+    })
+    .await?;
+    let results = res.json();
+
+    if let Some(status) = results.statuses.into_iter().next() {
+        return Ok(Navigation::Status { status });
+    }
+
+    if let Some(account) = results.accounts.into_iter().next() {
+        return Ok(Navigation::Profile { account });
+    }
+
+    Err(error::DakkoError::Invalid(format!(
+        "nothing found for {}",
+        url
+    )))
+}
+
+async fn open(handle: &tauri::AppHandle, uri: &str) -> Result<(), error::DakkoError> {
+    let url = reqwest::Url::parse(uri.trim())
+        .map_err(|err| error::DakkoError::Invalid(format!("not a valid link: {}", err)))?;
+
+    if !SCHEMES.contains(&url.scheme()) {
+        return Err(error::DakkoError::Invalid(format!(
+            "cannot open {} links",
+            url.scheme()
+        )));
+    }
+
+    if url.scheme() == "dakko" && url.host_str() == Some("oauth") {
+        auth::authorize_deep_link(handle, &url).await;
+        return Ok(());
+    }
+
+    let state = handle.state::<AppState>();
+    if !state.has_logged_in() {
+        return Err(error::DakkoError::Invalid(
+            "log in before opening links".to_string(),
+        ));
+    }
+
+    let navigation = resolve(state, classify(&url)?).await?;
+    navigate(handle, navigation);
+
+    Ok(())
+}
+
+/// Send the frontend somewhere and bring the window forward
+pub fn navigate(handle: &tauri::AppHandle, navigation: Navigation) {
+    if let Err(err) = handle.emit_all("navigate", navigation) {
+        eprintln!("Could not navigate: {}", err);
+    }
+
+    if let Some(window) = handle.get_window("main") {
+        let _ = window.set_focus();
+    }
+}
+
+/// Called by the OS for every link we are the handler for
+fn on_link(handle: tauri::AppHandle, uri: String) {
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = open(&handle, &uri).await {
+            eprintln!("Could not open {}: {}", uri, err);
+            handle.emit_all("navigate-failed", err.to_string()).unwrap();
+        }
+    });
+}
+
+pub fn register(app: &tauri::App) {
+    // Every registration also starts the plugin's listener for links a second instance hands
+    // over. Only the first listener can bind, and every one forwards to `on_link`, which tells
+    // the schemes apart. On Linux each registration rewrites the same .desktop file, so the
+    // scheme logins need goes last to keep its entry. On macOS both are declared in Info.plist.
+    for scheme in SCHEMES.iter().rev() {
+        let app_handle = app.handle();
+        let registered =
+            tauri_plugin_deep_link::register(scheme, move |uri| on_link(app_handle.clone(), uri));
+
+        if let Err(err) = registered {
+            eprintln!("Could not register as the {} handler: {}", scheme, err);
+        }
+    }
+
+    // On a cold start the OS hands us the link as an argument instead
+    *app.state::<AppState>().launch_link.lock() = std::env::args().skip(1).find(|arg| {
+        SCHEMES
+            .iter()
+            .any(|s| arg.starts_with(&format!("{}://", s)))
+    });
+}
+
+/// Open the link the app was launched with, if any. Called by the frontend once it is
+/// listening for navigation events.
+#[tauri::command]
+pub async fn open_launch_link(
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<(), error::DakkoError> {
+    let uri = state.launch_link.lock().take();
+    match uri {
+        Some(uri) => open(&handle, &uri).await,
+        None => Ok(()),
+    }
+}
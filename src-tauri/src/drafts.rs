@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::auth;
+use crate::config;
+use crate::error;
+use crate::settings;
+use crate::state::AppState;
+use crate::visibility::Visibility;
+
+/// Autosaves land in memory straight away, but reach the disk at most this often
+const WRITE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DraftPoll {
+    pub options: Vec<String>,
+    pub expires_in_secs: u64,
+    pub multiple: bool,
+}
+
+/// The part of a draft the composer edits
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DraftContent {
+    pub content: String,
+    pub cw: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub in_reply_to: Option<String>,
+    pub quoting: Option<String>,
+    pub media_ids: Vec<String>,
+    pub poll: Option<DraftPoll>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Draft {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub content: DraftContent,
+}
+
+#[derive(Default)]
+pub struct DraftStore {
+    /// Keyed by `user@instance`, so accounts don't see each other's drafts
+    drafts: RwLock<HashMap<String, Vec<Draft>>>,
+    last_written: Mutex<Option<Instant>>,
+    /// Whether a write is already waiting for the interval to pass
+    write_scheduled: Mutex<bool>,
+}
+
+pub fn load_drafts(state: &AppState) {
+    if let Some(drafts) = config::load_or_report(state, "drafts.json") {
+        *state.drafts.drafts.write() = drafts;
+    }
+}
+
+fn save_drafts(state: &AppState) -> Result<(), error::DakkoError> {
+    let config_dir = state.config_dir.read();
+    config::save(
+        config_dir.as_ref().unwrap(),
+        "drafts.json",
+        &*state.drafts.drafts.read(),
+    )?;
+
+    *state.drafts.last_written.lock() = Some(Instant::now());
+    Ok(())
+}
+
+/// Write the drafts now if we haven't recently, otherwise once the interval is up
+fn save_throttled(state: &AppState, handle: &tauri::AppHandle) -> Result<(), error::DakkoError> {
+    let wait = state
+        .drafts
+        .last_written
+        .lock()
+        .map(|at| WRITE_INTERVAL.saturating_sub(at.elapsed()))
+        .unwrap_or_default();
+
+    if wait.is_zero() {
+        return save_drafts(state);
+    }
+
+    let mut scheduled = state.drafts.write_scheduled.lock();
+    if !*scheduled {
+        *scheduled = true;
+
+        let handle = handle.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(wait).await;
+
+            let state = handle.state::<AppState>();
+            *state.drafts.write_scheduled.lock() = false;
+            if let Err(err) = save_drafts(&state) {
+                eprintln!("Could not save drafts: {}", err);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn account(state: &AppState) -> Result<String, error::DakkoError> {
+    settings::account_key(state)
+        .await
+        .ok_or_else(|| error::DakkoError::Invalid("log in to keep drafts".to_string()))
+}
+
+fn not_found(id: &str) -> error::DakkoError {
+    error::DakkoError::Invalid(format!("no draft {}", id))
+}
+
+/// The logged in account's drafts, most recently edited first
+#[tauri::command]
+pub async fn get_drafts(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Draft>, error::DakkoError> {
+    let account = account(&state).await?;
+
+    let mut drafts = state
+        .drafts
+        .drafts
+        .read()
+        .get(&account)
+        .cloned()
+        .unwrap_or_default();
+    drafts.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+
+    Ok(drafts)
+}
+
+#[tauri::command]
+pub async fn create_draft(
+    draft: DraftContent,
+    state: tauri::State<'_, AppState>,
+) -> Result<Draft, error::DakkoError> {
+    let account = account(&state).await?;
+
+    let now = Utc::now();
+    let draft = Draft {
+        id: auth::random_token(16),
+        created_at: now,
+        updated_at: now,
+        content: draft,
+    };
+
+    state
+        .drafts
+        .drafts
+        .write()
+        .entry(account)
+        .or_default()
+        .push(draft.clone());
+    save_drafts(&state)?;
+
+    Ok(draft)
+}
+
+/// Replace a draft's content. Meant to be called as the user types, so the write to disk
+/// is throttled.
+#[tauri::command]
+pub async fn update_draft(
+    id: String,
+    draft: DraftContent,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<Draft, error::DakkoError> {
+    let account = account(&state).await?;
+
+    let updated = {
+        let mut drafts = state.drafts.drafts.write();
+        let existing = drafts
+            .get_mut(&account)
+            .and_then(|drafts| drafts.iter_mut().find(|d| d.id == id))
+            .ok_or_else(|| not_found(&id))?;
+
+        existing.content = draft;
+        existing.updated_at = Utc::now();
+        existing.clone()
+    };
+
+    save_throttled(&state, &handle)?;
+    Ok(updated)
+}
+
+#[tauri::command]
+pub async fn delete_draft(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), error::DakkoError> {
+    let account = account(&state).await?;
+
+    {
+        let mut drafts = state.drafts.drafts.write();
+        let list = drafts.get_mut(&account).ok_or_else(|| not_found(&id))?;
+
+        let before = list.len();
+        list.retain(|d| d.id != id);
+        if list.len() == before {
+            return Err(not_found(&id));
+        }
+
+        if list.is_empty() {
+            drafts.remove(&account);
+        }
+    }
+
+    save_drafts(&state)
+}
@@ -1,17 +1,114 @@
-use serde::ser::{Serialize, Serializer};
-pub struct DakkoError(megalodon::error::Error);
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+pub enum DakkoError {
+    Megalodon(megalodon::error::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Http(reqwest::Error),
+    Status { status: u16, message: String },
+    Invalid(String),
+    /// The token was not granted a scope the command needs
+    MissingScope(String),
+    /// The write could not be sent and is waiting in the outbox under this id
+    Queued(String),
+    /// Out of rate limit budget for this many more seconds
+    RateLimited(u64),
+}
+
+impl DakkoError {
+    /// The HTTP status the server answered with, if the error came from a response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            DakkoError::Megalodon(megalodon::error::Error::OwnError(err)) => err.status,
+            DakkoError::Megalodon(megalodon::error::Error::RequestError(err)) => {
+                err.status().map(|s| s.as_u16())
+            }
+            DakkoError::Http(err) => err.status().map(|s| s.as_u16()),
+            DakkoError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Lets the frontend tell failures apart without matching on the message
+    fn kind(&self) -> &'static str {
+        match self {
+            DakkoError::Megalodon(_) | DakkoError::Http(_) | DakkoError::Status { .. } => "request",
+            DakkoError::Io(_) | DakkoError::Json(_) => "local",
+            DakkoError::Invalid(_) => "invalid",
+            DakkoError::MissingScope(_) => "missing_scope",
+            DakkoError::Queued(_) => "queued",
+            DakkoError::RateLimited(_) => "rate_limited",
+        }
+    }
+
+    /// Whether the server told us it has no such endpoint, as opposed to failing to answer
+    pub fn is_unimplemented(&self) -> bool {
+        matches!(self.status(), Some(404) | Some(501))
+    }
+}
+
+impl std::fmt::Display for DakkoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DakkoError::Megalodon(err) => write!(f, "{}", err),
+            DakkoError::Io(err) => write!(f, "{}", err),
+            DakkoError::Json(err) => write!(f, "{}", err),
+            DakkoError::Http(err) => write!(f, "{}", err),
+            DakkoError::Status { status, message } => write!(f, "{} ({})", message, status),
+            DakkoError::Invalid(message) => write!(f, "{}", message),
+            DakkoError::MissingScope(scope) => {
+                write!(f, "missing scope {} — re-authorise to grant it", scope)
+            }
+            DakkoError::Queued(_) => {
+                write!(f, "could not reach the instance, it will be sent once it can be")
+            }
+            DakkoError::RateLimited(secs) => {
+                write!(f, "rate limited by the instance, try again in {} seconds", secs)
+            }
+        }
+    }
+}
 
 impl Serialize for DakkoError {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_str(&self.0.to_string())
+        let fields = match self {
+            DakkoError::Queued(_) => 3,
+            _ => 2,
+        };
+        let mut error = serializer.serialize_struct("DakkoError", fields)?;
+        error.serialize_field("kind", self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        // Matches the `outbox-sent` event for the write once it goes through
+        if let DakkoError::Queued(id) = self {
+            error.serialize_field("id", id)?;
+        }
+        error.end()
     }
 }
 
 impl From<megalodon::error::Error> for DakkoError {
     fn from(value: megalodon::error::Error) -> Self {
-        Self(value)
+        Self::Megalodon(value)
+    }
+}
+
+impl From<std::io::Error> for DakkoError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for DakkoError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+impl From<reqwest::Error> for DakkoError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Http(value)
     }
 }
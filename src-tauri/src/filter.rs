@@ -0,0 +1,759 @@
+//! Local filter rules, written in a small expression language and evaluated before statuses
+//! ever reach the webview. A rule looks like:
+//!
+//! ```text
+//! (keyword("spoilers") or cw("game")) and not username("friend")
+//! ```
+//!
+//! Available predicates:
+//! - `keyword("text")`: content or CW contains the text, case insensitive
+//! - `word("text")`: content or CW contains the text as a whole word
+//! - `regex("pattern")`: content or CW matches the pattern
+//! - `cw` / `cw("text")`: status has a CW, optionally containing the text
+//! - `username("text")`: author (or booster) acct contains the text
+//! - `domain("example.com")`: author is on the given domain or one of its subdomains
+//! - `language("en", "de")`: status is tagged with one of the languages
+//! - `has_media`, `boost`, `reply`
+//! - `older_than("2h")` / `newer_than("30m")`: status age, in `m`, `h` or `d`
+//! - `always`: matches everything
+
+use chrono::{Duration, Utc};
+use megalodon::entities;
+use parking_lot::RwLock;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::error;
+use crate::quotes;
+use crate::relationship;
+use crate::settings;
+use crate::page::Page;
+use crate::server_filters::{self, FilterContext, FilteredStatus};
+use crate::state::AppState;
+use crate::text;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Show,
+    Warn,
+    Hide,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterRule {
+    pub name: String,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    pub rule: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleMatch {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Always,
+    Keyword(String),
+    Pattern(Regex),
+    Cw(Option<String>),
+    Username(String),
+    Domain(String),
+    Language(Vec<String>),
+    HasMedia,
+    Boost,
+    Reply,
+    OlderThan(Duration),
+    NewerThan(Duration),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    LParen,
+    RParen,
+    Comma,
+}
+
+#[derive(Debug)]
+struct ParseError {
+    message: String,
+    position: usize,
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+fn tokenize(source: &str) -> ParseResult<Vec<(usize, Token)>> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((position, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push((position, Token::LParen)),
+            ')' => tokens.push((position, Token::RParen)),
+            ',' => tokens.push((position, Token::Comma)),
+            '"' => {
+                let mut value = String::new();
+                let mut closed = false;
+
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => match chars.next() {
+                            Some((_, escaped)) => value.push(escaped),
+                            None => break,
+                        },
+                        '"' => {
+                            closed = true;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+
+                if !closed {
+                    return Err(ParseError {
+                        message: "unterminated string".to_string(),
+                        position,
+                    });
+                }
+
+                tokens.push((position, Token::Str(value)));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some((_, c)) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || *c == '_' {
+                        ident.push(*c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                tokens.push((position, Token::Ident(ident.to_lowercase())));
+            }
+            c => {
+                return Err(ParseError {
+                    message: format!("unexpected character '{}'", c),
+                    position,
+                })
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map(|(p, _)| *p)
+            .unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> ParseResult<T> {
+        Err(ParseError {
+            message: message.into(),
+            position: self.position(),
+        })
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(i)) if i == ident => {
+                self.index += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse(mut self) -> ParseResult<Condition> {
+        let condition = self.or()?;
+        if self.peek().is_some() {
+            return self.error("expected 'and', 'or' or the end of the rule");
+        }
+
+        Ok(condition)
+    }
+
+    fn or(&mut self) -> ParseResult<Condition> {
+        let mut lhs = self.and()?;
+        while self.eat_ident("or") {
+            let rhs = self.and()?;
+            lhs = Condition::Or(Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> ParseResult<Condition> {
+        let mut lhs = self.unary()?;
+        while self.eat_ident("and") {
+            let rhs = self.unary()?;
+            lhs = Condition::And(Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> ParseResult<Condition> {
+        if self.eat_ident("not") {
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+
+        if self.eat(&Token::LParen) {
+            let inner = self.or()?;
+            if !self.eat(&Token::RParen) {
+                return self.error("expected ')'");
+            }
+
+            return Ok(inner);
+        }
+
+        self.predicate()
+    }
+
+    fn args(&mut self) -> ParseResult<Vec<String>> {
+        let mut args = vec![];
+        if !self.eat(&Token::LParen) {
+            return Ok(args);
+        }
+
+        loop {
+            match self.peek().cloned() {
+                Some(Token::Str(value)) => {
+                    self.index += 1;
+                    args.push(value);
+                }
+                Some(Token::RParen) if args.is_empty() => {}
+                _ => return self.error("expected a string argument"),
+            }
+
+            if self.eat(&Token::Comma) {
+                continue;
+            }
+
+            if self.eat(&Token::RParen) {
+                return Ok(args);
+            }
+
+            return self.error("expected ',' or ')'");
+        }
+    }
+
+    fn predicate(&mut self) -> ParseResult<Condition> {
+        let position = self.position();
+        let name = match self.peek().cloned() {
+            Some(Token::Ident(name)) => {
+                self.index += 1;
+                name
+            }
+            _ => return self.error("expected a predicate"),
+        };
+
+        let args = self.args()?;
+        let single = |args: &[String]| -> ParseResult<String> {
+            match args {
+                [value] => Ok(value.clone()),
+                _ => Err(ParseError {
+                    message: format!("'{}' takes exactly one argument", name),
+                    position,
+                }),
+            }
+        };
+        let none = |args: &[String], condition: Condition| -> ParseResult<Condition> {
+            if args.is_empty() {
+                Ok(condition)
+            } else {
+                Err(ParseError {
+                    message: format!("'{}' takes no arguments", name),
+                    position,
+                })
+            }
+        };
+        let compile = |pattern: &str| -> ParseResult<Regex> {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| ParseError {
+                    message: format!("invalid pattern: {}", err),
+                    position,
+                })
+        };
+        let age = |value: &str| -> ParseResult<Duration> {
+            parse_age(value).ok_or_else(|| ParseError {
+                message: format!("invalid age '{}', expected e.g \"30m\", \"2h\" or \"7d\"", value),
+                position,
+            })
+        };
+
+        match name.as_str() {
+            "always" => none(&args, Condition::Always),
+            "keyword" => Ok(Condition::Keyword(single(&args)?.to_lowercase())),
+            "word" => Ok(Condition::Pattern(compile(&format!(
+                r"\b{}\b",
+                regex::escape(&single(&args)?)
+            ))?)),
+            "regex" => Ok(Condition::Pattern(compile(&single(&args)?)?)),
+            "cw" => match args.as_slice() {
+                [] => Ok(Condition::Cw(None)),
+                [value] => Ok(Condition::Cw(Some(value.to_lowercase()))),
+                _ => Err(ParseError {
+                    message: "'cw' takes at most one argument".to_string(),
+                    position,
+                }),
+            },
+            "username" => Ok(Condition::Username(single(&args)?.to_lowercase())),
+            "domain" => Ok(Condition::Domain(single(&args)?.to_lowercase())),
+            "language" => {
+                if args.is_empty() {
+                    return Err(ParseError {
+                        message: "'language' takes at least one argument".to_string(),
+                        position,
+                    });
+                }
+
+                Ok(Condition::Language(
+                    args.iter().map(|a| a.to_lowercase()).collect(),
+                ))
+            }
+            "has_media" => none(&args, Condition::HasMedia),
+            "boost" => none(&args, Condition::Boost),
+            "reply" => none(&args, Condition::Reply),
+            "older_than" => Ok(Condition::OlderThan(age(&single(&args)?)?)),
+            "newer_than" => Ok(Condition::NewerThan(age(&single(&args)?)?)),
+            _ => Err(ParseError {
+                message: format!("unknown predicate '{}'", name),
+                position,
+            }),
+        }
+    }
+}
+
+fn parse_age(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    // The unit is the last character, which need not be a single byte
+    let (split, unit) = value.char_indices().last()?;
+    let amount: i64 = value[..split].parse().ok()?;
+
+    match unit {
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        _ => None,
+    }
+}
+
+pub fn domain_of(account: &entities::Account) -> Option<String> {
+    if let Some((_, domain)) = account.acct.split_once('@') {
+        return Some(domain.to_lowercase());
+    }
+
+    reqwest::Url::parse(&account.url)
+        .ok()
+        .and_then(|url| url.host_str().map(|h| h.to_lowercase()))
+}
+
+impl Condition {
+    fn matches(&self, status: &entities::Status) -> bool {
+        // Content checks look at what is being shown, which for a boost is the boosted post
+        let shown = status.reblog.as_deref().unwrap_or(status);
+
+        match self {
+            Condition::Always => true,
+            Condition::Keyword(keyword) => {
+                text::strip_html(&shown.content)
+                    .to_lowercase()
+                    .contains(keyword)
+                    || shown.spoiler_text.to_lowercase().contains(keyword)
+            }
+            Condition::Pattern(re) => {
+                re.is_match(&text::strip_html(&shown.content)) || re.is_match(&shown.spoiler_text)
+            }
+            Condition::Cw(None) => !shown.spoiler_text.is_empty(),
+            Condition::Cw(Some(keyword)) => shown.spoiler_text.to_lowercase().contains(keyword),
+            Condition::Username(needle) => {
+                status.account.acct.to_lowercase().contains(needle)
+                    || shown.account.acct.to_lowercase().contains(needle)
+            }
+            Condition::Domain(domain) => [&status.account, &shown.account]
+                .iter()
+                .filter_map(|a| domain_of(a))
+                .any(|d| d == *domain || d.ends_with(&format!(".{}", domain))),
+            Condition::Language(languages) => shown
+                .language
+                .as_ref()
+                .map_or(false, |l| languages.contains(&l.to_lowercase())),
+            Condition::HasMedia => !shown.media_attachments.is_empty(),
+            Condition::Boost => status.reblog.is_some(),
+            Condition::Reply => shown.in_reply_to_id.is_some(),
+            Condition::OlderThan(age) => Utc::now() - shown.created_at > *age,
+            Condition::NewerThan(age) => Utc::now() - shown.created_at < *age,
+            Condition::Not(inner) => !inner.matches(status),
+            Condition::And(lhs, rhs) => lhs.matches(status) && rhs.matches(status),
+            Condition::Or(lhs, rhs) => lhs.matches(status) || rhs.matches(status),
+        }
+    }
+}
+
+fn parse(source: &str) -> ParseResult<Condition> {
+    Parser {
+        tokens: tokenize(source)?,
+        index: 0,
+        end: source.len(),
+    }
+    .parse()
+}
+
+struct CompiledRule {
+    rule: FilterRule,
+    condition: Condition,
+}
+
+fn compile(rule: FilterRule) -> Result<CompiledRule, error::DakkoError> {
+    match parse(&rule.rule) {
+        Ok(condition) => Ok(CompiledRule { rule, condition }),
+        Err(err) => Err(error::DakkoError::Invalid(format!(
+            "filter '{}': {} (at character {})",
+            rule.name, err.message, err.position
+        ))),
+    }
+}
+
+#[derive(Default)]
+pub struct FilterEngine {
+    rules: RwLock<Vec<CompiledRule>>,
+}
+
+impl FilterEngine {
+    fn set(&self, rules: Vec<FilterRule>) -> Result<(), error::DakkoError> {
+        let compiled = rules
+            .into_iter()
+            .map(compile)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        *self.rules.write() = compiled;
+        Ok(())
+    }
+
+    fn get(&self) -> Vec<FilterRule> {
+        self.rules.read().iter().map(|r| r.rule.clone()).collect()
+    }
+
+    /// The first enabled rule that matches decides what happens to a status
+    pub fn evaluate(&self, status: &entities::Status) -> Option<RuleMatch> {
+        self.rules
+            .read()
+            .iter()
+            .find(|r| r.rule.enabled && r.condition.matches(status))
+            .map(|r| RuleMatch {
+                name: r.rule.name.clone(),
+                outcome: r.rule.outcome,
+            })
+    }
+}
+
+/// Everything a list of statuses goes through before it is handed to the frontend
+pub async fn prepare(
+    state: &AppState,
+    context: FilterContext,
+    statuses: Vec<entities::Status>,
+) -> Vec<FilteredStatus> {
+    let statuses = relationship::without_blocked_domains(state, statuses).await;
+    let statuses = server_filters::annotate(state, context, statuses).await;
+    let mut statuses = apply(state, statuses);
+
+    // Threads and profiles are shown whole, only timelines leave out other languages
+    if matches!(context, FilterContext::Home | FilterContext::Public) {
+        let read_languages = settings::cached(state).read_languages;
+        statuses.retain(|status| reads(&read_languages, &status.status));
+    }
+
+    quotes::attach(state, &mut statuses).await;
+    statuses
+}
+
+/// `prepare` for a page of a timeline. The cursors come from the page as the instance sent
+/// it, so a page the filters emptied doesn't look like the end of the timeline.
+pub async fn prepare_page(
+    state: &AppState,
+    context: FilterContext,
+    statuses: Vec<entities::Status>,
+) -> Page<FilteredStatus> {
+    let next = statuses.last().map(|s| s.id.clone());
+    let prev = statuses.first().map(|s| s.id.clone());
+
+    Page {
+        items: prepare(state, context, statuses).await,
+        next,
+        prev,
+    }
+}
+
+/// Whether a status is in one of `languages`. Statuses that don't say what language they are
+/// in are given the benefit of the doubt.
+fn reads(languages: &[String], status: &entities::Status) -> bool {
+    let status = status.reblog.as_deref().unwrap_or(status);
+    match &status.language {
+        Some(language) if !languages.is_empty() => languages
+            .iter()
+            .any(|l| language.split(['-', '_']).next() == Some(l.as_str())),
+        _ => true,
+    }
+}
+
+/// Drop statuses hidden by a rule, and mark those that should be shown behind a warning.
+pub fn apply(state: &AppState, statuses: Vec<FilteredStatus>) -> Vec<FilteredStatus> {
+    statuses
+        .into_iter()
+        .filter_map(|mut status| {
+            let matched = state.filters.evaluate(&status.status);
+
+            match matched.as_ref().map(|m| m.outcome) {
+                Some(Outcome::Hide) => None,
+                Some(Outcome::Warn) => {
+                    status.rule = matched;
+                    Some(status)
+                }
+                _ => Some(status),
+            }
+        })
+        .collect()
+}
+
+pub fn load_rules(state: &AppState) {
+    if let Some(rules) = config::load_or_report::<Vec<FilterRule>>(state, "filters.json") {
+        // A rule we can no longer parse shouldn't take the others down with it
+        let compiled = rules
+            .into_iter()
+            .filter_map(|rule| match compile(rule) {
+                Ok(compiled) => Some(compiled),
+                Err(err) => {
+                    eprintln!("Skipping invalid filter rule: {}", err);
+                    None
+                }
+            })
+            .collect();
+
+        *state.filters.rules.write() = compiled;
+    }
+}
+
+fn save_rules(state: &AppState) -> Result<(), error::DakkoError> {
+    let config_dir = state.config_dir.read();
+    config::save(config_dir.as_ref().unwrap(), "filters.json", &state.filters.get())
+}
+
+#[tauri::command]
+pub async fn get_filter_rules(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<FilterRule>, error::DakkoError> {
+    Ok(state.filters.get())
+}
+
+#[tauri::command]
+pub async fn set_filter_rules(
+    rules: Vec<FilterRule>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<FilterRule>, error::DakkoError> {
+    state.filters.set(rules)?;
+    save_rules(&state)?;
+
+    Ok(state.filters.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_at(source: &str) -> (String, usize) {
+        let err = parse(source).unwrap_err();
+        (err.message, err.position)
+    }
+
+    #[test]
+    fn parses_predicates_and_arguments() {
+        assert!(matches!(parse("always"), Ok(Condition::Always)));
+        assert!(matches!(parse("cw"), Ok(Condition::Cw(None))));
+        assert!(matches!(parse("cw()"), Ok(Condition::Cw(None))));
+        assert!(matches!(
+            parse(r#"KEYWORD("Spoilers")"#),
+            Ok(Condition::Keyword(k)) if k == "spoilers"
+        ));
+        assert!(matches!(
+            parse(r#"username("say \"hi\"")"#),
+            Ok(Condition::Username(u)) if u == "say \"hi\""
+        ));
+        assert!(matches!(
+            parse(r#"language("en", "DE")"#),
+            Ok(Condition::Language(l)) if l == ["en", "de"]
+        ));
+        assert!(matches!(
+            parse(r#"word("a.b")"#),
+            Ok(Condition::Pattern(re)) if re.is_match("x A.B y") && !re.is_match("aXb")
+        ));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        match parse("boost or reply and has_media").unwrap() {
+            Condition::Or(lhs, rhs) => {
+                assert!(matches!(*lhs, Condition::Boost));
+                assert!(matches!(*rhs, Condition::And(..)));
+            }
+            other => panic!("parsed as {:?}", other),
+        }
+
+        match parse("(boost or reply) and has_media").unwrap() {
+            Condition::And(lhs, rhs) => {
+                assert!(matches!(*lhs, Condition::Or(..)));
+                assert!(matches!(*rhs, Condition::HasMedia));
+            }
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn not_applies_to_the_nearest_term() {
+        match parse("not boost and reply").unwrap() {
+            Condition::And(lhs, rhs) => {
+                assert!(matches!(*lhs, Condition::Not(_)));
+                assert!(matches!(*rhs, Condition::Reply));
+            }
+            other => panic!("parsed as {:?}", other),
+        }
+
+        assert!(matches!(parse("not not boost"), Ok(Condition::Not(_))));
+    }
+
+    #[test]
+    fn operators_chain_left_to_right() {
+        match parse("boost or reply or cw").unwrap() {
+            Condition::Or(lhs, rhs) => {
+                assert!(matches!(*lhs, Condition::Or(..)));
+                assert!(matches!(*rhs, Condition::Cw(None)));
+            }
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(
+            error_at(r#"keyword("a"#),
+            ("unterminated string".to_string(), 8)
+        );
+        assert_eq!(
+            error_at("boost $"),
+            ("unexpected character '$'".to_string(), 6)
+        );
+        assert_eq!(
+            error_at("boost and"),
+            ("expected a predicate".to_string(), 9)
+        );
+        assert_eq!(
+            error_at("(boost or reply"),
+            ("expected ')'".to_string(), 15)
+        );
+        assert_eq!(
+            error_at("boost reply"),
+            ("expected 'and', 'or' or the end of the rule".to_string(), 6)
+        );
+        assert_eq!(
+            error_at(r#"reply and nope("x")"#),
+            ("unknown predicate 'nope'".to_string(), 10)
+        );
+        assert_eq!(
+            error_at(r#"keyword("a", "b")"#),
+            ("'keyword' takes exactly one argument".to_string(), 0)
+        );
+        assert_eq!(
+            error_at(r#"boost("a")"#),
+            ("'boost' takes no arguments".to_string(), 0)
+        );
+        assert_eq!(
+            error_at("keyword(a)"),
+            ("expected a string argument".to_string(), 8)
+        );
+        assert_eq!(
+            error_at("language()"),
+            ("'language' takes at least one argument".to_string(), 0)
+        );
+
+        let (message, position) = error_at(r#"cw or regex("(")"#);
+        assert!(message.starts_with("invalid pattern"));
+        assert_eq!(position, 6);
+    }
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_age(" 2h "), Some(Duration::hours(2)));
+        assert_eq!(parse_age("7d"), Some(Duration::days(7)));
+        assert_eq!(parse_age("0m"), Some(Duration::zero()));
+
+        assert_eq!(parse_age(""), None);
+        assert_eq!(parse_age("h"), None);
+        assert_eq!(parse_age("10"), None);
+        assert_eq!(parse_age("5s"), None);
+        assert_eq!(parse_age("1.5h"), None);
+        assert_eq!(parse_age("2 h"), None);
+        assert_eq!(parse_age("99999999999999d"), None);
+
+        assert!(matches!(
+            parse(r#"older_than("1d")"#),
+            Ok(Condition::OlderThan(age)) if age == Duration::days(1)
+        ));
+        assert_eq!(
+            error_at(r#"newer_than("soon")"#),
+            (
+                "invalid age 'soon', expected e.g \"30m\", \"2h\" or \"7d\"".to_string(),
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_ages_ending_in_wide_characters() {
+        assert_eq!(parse_age("2ä"), None);
+        assert_eq!(parse_age("ä"), None);
+        assert_eq!(parse_age("3日"), None);
+        assert_eq!(parse_age("2m😀"), None);
+        assert!(parse(r#"older_than("2ä")"#).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use reqwest::multipart::Form;
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error;
+use crate::request::{self, RateLimiter};
+use crate::state::AppState;
+
+/// A thin authenticated client for the endpoints megalodon does not wrap.
+pub struct HttpClient {
+    client: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+    rate_limit: Arc<RateLimiter>,
+    idempotency_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl HttpClient {
+    pub fn from_state(state: &AppState) -> Self {
+        let base_url = state
+            .client_state
+            .read()
+            .as_ref()
+            .unwrap()
+            .base_url
+            .trim_end_matches('/')
+            .to_string();
+
+        let token = state
+            .auth_state
+            .read()
+            .as_ref()
+            .map(|auth| auth.token.access_token.clone());
+
+        Self {
+            client: state.http.clone(),
+            base_url,
+            token,
+            rate_limit: state.rate_limit.clone(),
+            idempotency_key: None,
+        }
+    }
+
+    /// Send an `Idempotency-Key` with requests, so the instance ignores repeats of a write
+    /// it has already acted on
+    pub fn idempotency_key(mut self, key: &str) -> Self {
+        self.idempotency_key = Some(key.to_string());
+        self
+    }
+
+    /// Send a request, retrying GETs that fail transiently and holding writes back while we
+    /// are rate limited
+    pub async fn send<B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<&B>,
+    ) -> Result<reqwest::Response, error::DakkoError> {
+        let attempt = || self.send_once(method.clone(), path, query, body);
+
+        if method == Method::GET {
+            request::read(&self.rate_limit, attempt).await
+        } else {
+            request::write(&self.rate_limit, attempt).await
+        }
+    }
+
+    /// Send a multipart form, for requests that upload files. `form` is called for every
+    /// attempt, as a form can only be sent once
+    pub async fn send_form<T: DeserializeOwned, F: Fn() -> Form>(
+        &self,
+        method: Method,
+        path: &str,
+        form: F,
+    ) -> Result<T, error::DakkoError> {
+        let res = request::write(&self.rate_limit, || {
+            self.execute(self.request(method.clone(), path, &[]).multipart(form()))
+        })
+        .await?;
+        Ok(res.json().await?)
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> reqwest::RequestBuilder {
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path))
+            .query(query);
+
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        if let Some(key) = &self.idempotency_key {
+            request = request.header("Idempotency-Key", key);
+        }
+
+        request
+    }
+
+    async fn send_once<B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<&B>,
+    ) -> Result<reqwest::Response, error::DakkoError> {
+        let mut request = self.request(method, path, query);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        self.execute(request).await
+    }
+
+    async fn execute(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, error::DakkoError> {
+        let res = request.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            // Error responses still count against the limit
+            self.rate_limit.record(res.headers());
+
+            let message = match res.json::<ErrorBody>().await {
+                Ok(body) => body.error,
+                Err(_) => status.canonical_reason().unwrap_or("unknown error").to_string(),
+            };
+
+            return Err(error::DakkoError::Status {
+                status: status.as_u16(),
+                message,
+            });
+        }
+
+        Ok(res)
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, error::DakkoError> {
+        let res = self.send::<()>(Method::GET, path, query, None).await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn post<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, error::DakkoError> {
+        let res = self.send(Method::POST, path, &[], Some(body)).await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn put<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, error::DakkoError> {
+        let res = self.send(Method::PUT, path, &[], Some(body)).await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<(), error::DakkoError> {
+        self.send::<()>(Method::DELETE, path, &[], None).await?;
+        Ok(())
+    }
+}
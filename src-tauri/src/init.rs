@@ -1,41 +1,38 @@
-use std::fs::File;
-
 use megalodon::generator;
 use tauri::Manager;
 
-use crate::state::{AppState, AuthState, ClientState};
+use crate::{
+    config, deep_link, drafts, filter, notifier, outbox, settings,
+    state::{AppState, AuthState, ClientState},
+};
 
 pub fn setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     let config_dir = app.handle().path_resolver().app_config_dir().unwrap();
     let state = app.state::<AppState>();
 
+    *state.config_dir.write() = Some(config_dir);
+
     {
-        let path = config_dir.join("client.json");
-        let file = File::open(path);
-        if let Ok(file) = file {
-            let client_state = serde_json::from_reader::<_, ClientState>(file).unwrap();
+        // A file we cannot read is reported and treated as missing, so the worst case is
+        // having to log in again
+        if let Some(client_state) = config::load_or_report::<ClientState>(&state, "client.json") {
             let url = client_state.base_url.clone();
             *state.client_state.write() = Some(client_state);
 
             let mut client = generator(megalodon::SNS::Pleroma, url.clone(), None, None);
-            {
-                let path = config_dir.join("auth.json");
-                let file = File::open(path);
-                if let Ok(file) = file {
-                    let auth_state = serde_json::from_reader::<_, AuthState>(file).unwrap();
-                    client = generator(
-                        megalodon::SNS::Pleroma,
-                        url,
-                        Some(auth_state.token.access_token.clone()),
-                        None,
-                    );
-
-                    // TODO: Use this to refresh the tokens once we want to do that
-                    // tauri::async_runtime::block_on(async {
-                    // });
-
-                    *state.auth_state.write() = Some(auth_state);
-                }
+            if let Some(auth_state) = config::load_or_report::<AuthState>(&state, "auth.json") {
+                client = generator(
+                    megalodon::SNS::Pleroma,
+                    url,
+                    Some(auth_state.token.access_token.clone()),
+                    None,
+                );
+
+                // TODO: Use this to refresh the tokens once we want to do that
+                // tauri::async_runtime::block_on(async {
+                // });
+
+                *state.auth_state.write() = Some(auth_state);
             }
 
             *state.client.write() = Some(client);
@@ -44,6 +41,14 @@ pub fn setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    *state.config_dir.write() = Some(config_dir);
+    notifier::load_settings(&state);
+    filter::load_rules(&state);
+    settings::load_settings(&state);
+    outbox::load_outbox(&state);
+    drafts::load_drafts(&state);
+    tauri::async_runtime::spawn(notifier::run(app.handle()));
+    tauri::async_runtime::spawn(outbox::run(app.handle()));
+    deep_link::register(app);
+
     Ok(())
 }
\ No newline at end of file
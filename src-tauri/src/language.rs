@@ -0,0 +1,167 @@
+use crate::error;
+use crate::text;
+
+/// whatlang names languages by ISO 639-3, instances want ISO 639-1
+fn iso_639_1(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "afr" => "af",
+        "aka" => "ak",
+        "amh" => "am",
+        "ara" => "ar",
+        "aze" => "az",
+        "bel" => "be",
+        "ben" => "bn",
+        "bul" => "bg",
+        "cat" => "ca",
+        "ces" => "cs",
+        "cmn" => "zh",
+        "dan" => "da",
+        "deu" => "de",
+        "ell" => "el",
+        "eng" => "en",
+        "epo" => "eo",
+        "est" => "et",
+        "fin" => "fi",
+        "fra" => "fr",
+        "guj" => "gu",
+        "heb" => "he",
+        "hin" => "hi",
+        "hrv" => "hr",
+        "hun" => "hu",
+        "hye" => "hy",
+        "ind" => "id",
+        "ita" => "it",
+        "jav" => "jv",
+        "jpn" => "ja",
+        "kan" => "kn",
+        "kat" => "ka",
+        "khm" => "km",
+        "kor" => "ko",
+        "lat" => "la",
+        "lav" => "lv",
+        "lit" => "lt",
+        "mal" => "ml",
+        "mar" => "mr",
+        "mkd" => "mk",
+        "mya" => "my",
+        "nep" => "ne",
+        "nld" => "nl",
+        "nob" => "nb",
+        "ori" => "or",
+        "pan" => "pa",
+        "pes" => "fa",
+        "pol" => "pl",
+        "por" => "pt",
+        "ron" => "ro",
+        "rus" => "ru",
+        "sin" => "si",
+        "slk" => "sk",
+        "slv" => "sl",
+        "sna" => "sn",
+        "spa" => "es",
+        "srp" => "sr",
+        "swe" => "sv",
+        "tam" => "ta",
+        "tel" => "te",
+        "tgl" => "tl",
+        "tha" => "th",
+        "tuk" => "tk",
+        "tur" => "tr",
+        "ukr" => "uk",
+        "urd" => "ur",
+        "uzb" => "uz",
+        "vie" => "vi",
+        "yid" => "yi",
+        "zul" => "zu",
+        _ => return None,
+    })
+}
+
+/// Drop what says nothing about the language a post is written in
+fn prose(text: &str) -> String {
+    text::strip_html(text)
+        .split_whitespace()
+        .filter(|word| {
+            !word.starts_with('@')
+                && !word.starts_with('#')
+                && !(word.starts_with(':') && word.ends_with(':'))
+                && !word.contains("://")
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The language `text` is written in, if we can tell with some confidence. Runs entirely
+/// offline.
+pub fn detect(text: &str) -> Option<&'static str> {
+    let info = whatlang::detect(&prose(text))?;
+    if !info.is_reliable() {
+        return None;
+    }
+
+    iso_639_1(info.lang().code())
+}
+
+/// Whether `code` looks like an ISO 639 language code
+pub fn is_code(code: &str) -> bool {
+    (2..=3).contains(&code.len()) && code.chars().all(|c| c.is_ascii_lowercase())
+}
+
+/// Suggest a language for a post being written
+#[tauri::command]
+pub async fn detect_language(text: String) -> Result<Option<String>, error::DakkoError> {
+    Ok(detect(&text).map(String::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_every_detectable_language() {
+        for lang in whatlang::Lang::all() {
+            let code = iso_639_1(lang.code());
+            assert!(
+                code.map_or(false, is_code),
+                "{} has no ISO 639-1 code",
+                lang.code()
+            );
+        }
+    }
+
+    #[test]
+    fn maps_iso_639_3_to_639_1() {
+        assert_eq!(iso_639_1("eng"), Some("en"));
+        assert_eq!(iso_639_1("cmn"), Some("zh"));
+        assert_eq!(iso_639_1("pes"), Some("fa"));
+        assert_eq!(iso_639_1("xyz"), None);
+    }
+
+    #[test]
+    fn prose_leaves_out_what_is_not_language() {
+        assert_eq!(
+            prose(
+                r#"<p>Hello <a href="https://a.example/@b">@b</a> #rust :blobcat: there https://example.com</p>"#
+            ),
+            "Hello there"
+        );
+    }
+
+    #[test]
+    fn detects_only_with_confidence() {
+        assert_eq!(
+            detect("This is a rather long sentence, written plainly in the English language."),
+            Some("en")
+        );
+        assert_eq!(detect("@someone #tag :emoji:"), None);
+    }
+
+    #[test]
+    fn recognises_codes() {
+        assert!(is_code("en"));
+        assert!(is_code("fil"));
+        assert!(!is_code("EN"));
+        assert!(!is_code("en-GB"));
+        assert!(!is_code("e"));
+    }
+}
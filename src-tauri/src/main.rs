@@ -4,31 +4,76 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use megalodon::{generator, SNS};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use state::{AppState, ClientState};
 
 mod auth;
 mod state;
 mod relationship;
+mod relationship_cache;
 mod status;
 mod timeline;
 mod user;
 mod init;
 mod error;
+mod notifier;
+mod http;
+mod server_filters;
+mod filter;
+mod page;
+mod transfer;
+mod text;
+mod deep_link;
+mod config;
+mod settings;
+mod request;
+mod outbox;
+mod drafts;
+mod quotes;
+mod language;
+mod capabilities;
+mod markdown;
+mod visibility;
 
 
 fn main() {
-    let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9119);
+    // Port 0 so the OAuth callback server gets whatever port is free at login time
+    let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+
+    // Links opened while we are already running get forwarded to this instance
+    tauri_plugin_deep_link::prepare("me.nullishamy.dakko");
 
     tauri::Builder::default()
         .setup(init::setup)
         .invoke_handler(tauri::generate_handler![
             set_instance,
             auth::login,
+            auth::submit_auth_code,
+            deep_link::open_launch_link,
+            config::take_config_errors,
+            settings::get_settings,
+            settings::update_settings,
+            request::get_rate_limit,
+            outbox::get_outbox,
+            outbox::retry_outbox,
+            outbox::discard_outbox_entry,
+            drafts::get_drafts,
+            drafts::create_draft,
+            drafts::update_draft,
+            drafts::delete_draft,
+            quotes::get_quote_support,
+            language::detect_language,
+            capabilities::get_capabilities,
+            markdown::preview_markdown,
             auth::login_state,
             user::get_instance,
             user::get_statuses,
+            user::get_account,
+            user::lookup_account,
+            user::get_followers,
+            user::get_following,
             user::get_user,
+            user::update_credentials,
             user::get_notifications,
             user::get_bookmarks,
             user::get_emojis,
@@ -40,6 +85,8 @@ fn main() {
             status::bookmark_status,
             status::unbookmark_status,
             status::vote_for_poll,
+            status::mute_conversation,
+            status::unmute_conversation,
             timeline::get_home_timeline,
             timeline::get_public_timeline,
             timeline::get_conversation,
@@ -53,11 +100,32 @@ fn main() {
             relationship::unblock_user,
             relationship::mute_user,
             relationship::unmute_user,
+            relationship::get_mutes,
+            relationship::get_blocks,
+            relationship::get_domain_blocks,
+            relationship::block_domain,
+            relationship::unblock_domain,
+            relationship::set_account_note,
+            relationship::endorse_account,
+            relationship::unendorse_account,
+            relationship::get_endorsements,
             relationship::follow_user,
             relationship::unfollow_user,
             relationship::get_follow_requests,
             relationship::accept_follow_request,
             relationship::deny_follow_request,
+            relationship::accept_follow_requests,
+            relationship::deny_follow_requests,
+            notifier::get_notifier_settings,
+            notifier::update_notifier_settings,
+            server_filters::get_server_filters,
+            server_filters::create_server_filter,
+            server_filters::update_server_filter,
+            server_filters::delete_server_filter,
+            filter::get_filter_rules,
+            filter::set_filter_rules,
+            transfer::export_data,
+            transfer::import_data,
         ])
         .manage(AppState {
             client: RwLock::new(None),
@@ -65,6 +133,22 @@ fn main() {
             auth_state: RwLock::new(None),
             config_dir: RwLock::new(None),
             redirect_addr: socket_addr,
+            pending_login: Mutex::new(None),
+            launch_link: Mutex::new(None),
+            config_errors: Mutex::new(vec![]),
+            notifier: Default::default(),
+            http: reqwest::Client::new(),
+            server_filters: RwLock::new(None),
+            filters: Default::default(),
+            domain_blocks: RwLock::new(None),
+            relationships: Default::default(),
+            settings: Default::default(),
+            rate_limit: Default::default(),
+            outbox: Default::default(),
+            drafts: Default::default(),
+            recent_posts: Default::default(),
+            quotes: Default::default(),
+            capabilities: Default::default(),
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -83,6 +167,13 @@ async fn set_instance(url: String, instance_type: SNS, state: tauri::State<'_, A
     });
 
     *client = Some(generator(instance_type, url, None, None));
+    *state.server_filters.write() = None;
+    *state.domain_blocks.write() = None;
+    state.relationships.invalidate_all();
+    state.settings.reset_account();
+    state.quotes.reset();
+    state.capabilities.reset();
+    state.rate_limit.reset();
 
     Ok(())
 }
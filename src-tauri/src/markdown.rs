@@ -0,0 +1,108 @@
+//! Markdown previews for the composer.
+//!
+//! Pleroma and Akkoma render Markdown posts server side, keep line breaks, and scrub the
+//! result down to an allowlist of tags. The preview approximates that: inline tags the
+//! default scrubber keeps are passed through without their attributes, and any other raw
+//! HTML is shown as written. Mentions and hashtags are left as text, only the server can
+//! link them.
+
+use pulldown_cmark::{html, Event, Options, Parser, Tag, TagEnd};
+
+use crate::error;
+
+/// Inline tags Pleroma's default scrubber lets through
+const ALLOWED_TAGS: [&str; 13] = [
+    "abbr", "acronym", "b", "br", "code", "del", "em", "i", "s", "strong", "sub", "sup", "u",
+];
+
+/// `raw` as a bare tag if it is one the server keeps
+fn allowed_tag(raw: &str) -> Option<String> {
+    let inner = raw.strip_prefix('<')?.strip_suffix('>')?;
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(inner) => (true, inner),
+        None => (false, inner),
+    };
+
+    let name = inner
+        .trim_end_matches('/')
+        .split_whitespace()
+        .next()?
+        .to_ascii_lowercase();
+    if !ALLOWED_TAGS.contains(&name.as_str()) {
+        return None;
+    }
+
+    Some(match (closing, name.as_str()) {
+        (_, "br") => "<br />".to_string(),
+        (true, _) => format!("</{}>", name),
+        (false, _) => format!("<{}>", name),
+    })
+}
+
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES;
+
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        // Posts keep the line breaks they were written with
+        Event::SoftBreak => Event::HardBreak,
+        Event::InlineHtml(raw) => match allowed_tag(&raw) {
+            Some(tag) => Event::InlineHtml(tag.into()),
+            // Shown as written rather than rendered, the server would strip it
+            None => Event::Text(raw),
+        },
+        Event::Html(raw) => Event::Text(raw),
+        // Images are not embedded in posts, just linked
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }),
+        Event::End(TagEnd::Image) => Event::End(TagEnd::Link),
+        event => event,
+    });
+
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, events);
+    out
+}
+
+#[tauri::command]
+pub async fn preview_markdown(content: String) -> Result<String, error::DakkoError> {
+    Ok(render(&content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_inline_tags_the_server_allows() {
+        assert_eq!(
+            render("some <b>bold</b>, <sub onclick=\"steal()\">low</sub><br/>text"),
+            "<p>some <b>bold</b>, <sub>low</sub><br />text</p>\n"
+        );
+    }
+
+    #[test]
+    fn shows_other_html_as_written() {
+        assert_eq!(
+            render("a <marquee>moving</marquee> post"),
+            "<p>a &lt;marquee&gt;moving&lt;/marquee&gt; post</p>\n"
+        );
+        assert_eq!(
+            render("<script>alert(1)</script>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+    }
+
+    #[test]
+    fn keeps_line_breaks() {
+        assert_eq!(render("one\ntwo"), "<p>one<br />\ntwo</p>\n");
+    }
+}
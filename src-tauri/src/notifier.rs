@@ -0,0 +1,500 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chrono::Timelike;
+use megalodon::entities::{self, notification::NotificationType};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tauri::{api::notification::Notification, Manager};
+
+use crate::auth;
+use crate::config;
+use crate::deep_link::{self, Navigation};
+use crate::error;
+use crate::request;
+use crate::settings;
+use crate::state::AppState;
+use crate::text;
+
+/// How many notifications to ask for at a time when catching up
+const PAGE_SIZE: u32 = 40;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start_hour: u32,
+    pub end_hour: u32,
+}
+
+impl QuietHours {
+    fn contains(&self, hour: u32) -> bool {
+        if self.start_hour <= self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            // The range wraps midnight, e.g 22 -> 7
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotifierSettings {
+    pub enabled: bool,
+    pub mentions: bool,
+    pub follows: bool,
+    pub follow_requests: bool,
+    pub favourites: bool,
+    pub boosts: bool,
+    pub quiet_hours: Option<QuietHours>,
+    pub max_per_minute: u32,
+    pub poll_interval_secs: u64,
+}
+
+impl Default for NotifierSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mentions: true,
+            follows: true,
+            follow_requests: true,
+            favourites: false,
+            boosts: false,
+            quiet_hours: None,
+            max_per_minute: 5,
+            poll_interval_secs: 30,
+        }
+    }
+}
+
+impl NotifierSettings {
+    fn validate(&self) -> Result<(), error::DakkoError> {
+        if let Some(quiet) = &self.quiet_hours {
+            if quiet.start_hour > 23 || quiet.end_hour > 23 {
+                return Err(error::DakkoError::Invalid(
+                    "quiet hours must be between 0 and 23".to_string(),
+                ));
+            }
+        }
+
+        if self.max_per_minute == 0 {
+            return Err(error::DakkoError::Invalid(
+                "max_per_minute must be at least 1".to_string(),
+            ));
+        }
+
+        if self.poll_interval_secs < 10 {
+            return Err(error::DakkoError::Invalid(
+                "poll_interval_secs must be at least 10".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn wants(&self, kind: &NotificationType) -> bool {
+        match kind {
+            NotificationType::Mention => self.mentions,
+            NotificationType::Follow => self.follows,
+            NotificationType::FollowRequest => self.follow_requests,
+            NotificationType::Favourite => self.favourites,
+            NotificationType::Reblog => self.boosts,
+            _ => false,
+        }
+    }
+}
+
+/// What a notification opens when clicked
+#[derive(Debug, Clone)]
+pub enum NotificationTarget {
+    Status(String),
+    Account(String),
+}
+
+#[derive(Default)]
+pub struct Notifier {
+    pub settings: RwLock<NotifierSettings>,
+    /// Last seen notification id, keyed by account
+    cursors: RwLock<HashMap<String, String>>,
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+impl Notifier {
+    fn try_acquire(&self, max_per_minute: u32) -> bool {
+        let mut recent = self.recent.lock();
+        let now = Instant::now();
+
+        while recent
+            .front()
+            .map_or(false, |t| now.duration_since(*t) >= Duration::from_secs(60))
+        {
+            recent.pop_front();
+        }
+
+        if recent.len() as u32 >= max_per_minute {
+            return false;
+        }
+
+        recent.push_back(now);
+        true
+    }
+}
+
+pub fn load_settings(state: &AppState) {
+    if let Some(settings) = config::load_or_report::<NotifierSettings>(state, "notifier.json") {
+        *state.notifier.settings.write() = settings;
+    }
+}
+
+fn save_settings(state: &AppState) -> Result<(), error::DakkoError> {
+    let config_dir = state.config_dir.read();
+    config::save(
+        config_dir.as_ref().unwrap(),
+        "notifier.json",
+        &*state.notifier.settings.read(),
+    )
+}
+
+#[tauri::command]
+pub async fn get_notifier_settings(
+    state: tauri::State<'_, AppState>,
+) -> Result<NotifierSettings, error::DakkoError> {
+    Ok(state.notifier.settings.read().clone())
+}
+
+#[tauri::command]
+pub async fn update_notifier_settings(
+    settings: NotifierSettings,
+    state: tauri::State<'_, AppState>,
+) -> Result<NotifierSettings, error::DakkoError> {
+    settings.validate()?;
+    *state.notifier.settings.write() = settings.clone();
+    save_settings(&state)?;
+
+    Ok(settings)
+}
+
+pub async fn run(handle: tauri::AppHandle) {
+    loop {
+        let interval = handle
+            .state::<AppState>()
+            .notifier
+            .settings
+            .read()
+            .poll_interval_secs;
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+
+        if let Err(err) = poll(&handle).await {
+            eprintln!("Notifier poll failed: {}", err);
+        }
+    }
+}
+
+async fn poll(handle: &tauri::AppHandle) -> Result<(), error::DakkoError> {
+    let state = handle.state::<AppState>();
+    if !state.has_logged_in() {
+        return Ok(());
+    }
+
+    let settings = state.notifier.settings.read().clone();
+    if !settings.enabled || auth::require_scope(&state, "read:notifications").is_err() {
+        return Ok(());
+    }
+
+    let key = match settings::account_key(&state).await {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let since = state.notifier.cursors.read().get(&key).cloned();
+
+    let notifications = fetch_since(&state, since.clone()).await?;
+
+    if let Some(newest) = notifications.first() {
+        state
+            .notifier
+            .cursors
+            .write()
+            .insert(key, newest.id.clone());
+    }
+
+    // The first poll for an account only establishes where we are, we don't want to
+    // replay its entire notification history at the user
+    if since.is_none() {
+        return Ok(());
+    }
+
+    if let Some(quiet) = &settings.quiet_hours {
+        if quiet.contains(chrono::Local::now().hour()) {
+            return Ok(());
+        }
+    }
+
+    let mut suppressed = 0;
+
+    // Notifications come back newest first, raise them in the order they happened
+    for notification in notifications.iter().rev() {
+        if !settings.wants(&notification.r#type) {
+            continue;
+        }
+
+        let (title, body, target) = match describe(notification) {
+            Some(described) => described,
+            None => continue,
+        };
+
+        if !state.notifier.try_acquire(settings.max_per_minute) {
+            suppressed += 1;
+            continue;
+        }
+
+        if let Err(err) = raise(handle, &title, &body, target) {
+            eprintln!("Could not raise notification: {}", err);
+        }
+    }
+
+    if suppressed > 0 {
+        let body = format!("{} more notifications were held back to avoid flooding you", suppressed);
+        let identifier = handle.config().tauri.bundle.identifier.clone();
+        if let Err(err) = Notification::new(&identifier).title("dakko").body(body).show() {
+            eprintln!("Could not raise notification: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Show a notification that opens `target` when clicked. Only freedesktop notification servers
+/// say when one is clicked, on macOS and Windows it is shown without that.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn raise(
+    handle: &tauri::AppHandle,
+    title: &str,
+    body: &str,
+    target: NotificationTarget,
+) -> Result<(), String> {
+    let shown = notify_rust::Notification::new()
+        .appname(&handle.package_info().name)
+        .summary(title)
+        .body(body)
+        .action("default", "Open")
+        .show()
+        .map_err(|err| err.to_string())?;
+
+    // Blocks until the notification is clicked or dismissed
+    let handle = handle.clone();
+    std::thread::spawn(move || {
+        shown.wait_for_action(|action| {
+            if action == "default" {
+                tauri::async_runtime::spawn(open_target(handle, target));
+            }
+        })
+    });
+
+    Ok(())
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn raise(
+    handle: &tauri::AppHandle,
+    title: &str,
+    body: &str,
+    _target: NotificationTarget,
+) -> Result<(), String> {
+    let identifier = handle.config().tauri.bundle.identifier.clone();
+    Notification::new(&identifier)
+        .title(title)
+        .body(body)
+        .show()
+        .map_err(|err| err.to_string())
+}
+
+/// Show what a clicked notification was about
+#[cfg_attr(not(all(unix, not(target_os = "macos"))), allow(dead_code))]
+async fn open_target(handle: tauri::AppHandle, target: NotificationTarget) {
+    let state = handle.state::<AppState>();
+    if !state.has_logged_in() {
+        return;
+    }
+
+    let navigation = {
+        let client = state.client.read();
+        let client = client.as_ref().unwrap();
+
+        match &target {
+            NotificationTarget::Status(id) => {
+                request::read(&state.rate_limit, || client.get_status(id.clone()))
+                    .await
+                    .map(|res| Navigation::Status { status: res.json() })
+            }
+            NotificationTarget::Account(id) => {
+                request::read(&state.rate_limit, || client.get_account(id.clone()))
+                    .await
+                    .map(|res| Navigation::Profile {
+                        account: res.json(),
+                    })
+            }
+        }
+    };
+
+    match navigation {
+        Ok(navigation) => deep_link::navigate(&handle, navigation),
+        Err(err) => eprintln!("Could not open notification: {}", err),
+    }
+}
+
+/// Every notification newer than `since`, newest first. Without a `since`, only the latest
+/// page, which is all the first poll needs to find its place.
+async fn fetch_since(
+    state: &AppState,
+    since: Option<String>,
+) -> Result<Vec<entities::Notification>, error::DakkoError> {
+    let mut notifications: Vec<entities::Notification> = vec![];
+
+    loop {
+        let options = megalodon::megalodon::GetNotificationsInputOptions {
+            limit: Some(PAGE_SIZE),
+            since_id: since.clone(),
+            max_id: notifications.last().map(|n| n.id.clone()),
+            ..Default::default()
+        };
+
+        let page = {
+            let client = state.client.read();
+            let client = client.as_ref().unwrap();
+
+            request::read(&state.rate_limit, || client.get_notifications(Some(&options)))
+                .await?
+                .json()
+        };
+
+        let done = since.is_none() || (page.len() as u32) < PAGE_SIZE;
+        notifications.extend(page);
+        if done {
+            return Ok(notifications);
+        }
+    }
+}
+
+fn describe(notification: &entities::Notification) -> Option<(String, String, NotificationTarget)> {
+    let account = notification.account.as_ref()?;
+    let name = if account.display_name.is_empty() {
+        account.acct.clone()
+    } else {
+        account.display_name.clone()
+    };
+
+    let account_target = NotificationTarget::Account(account.id.clone());
+    let status_target = notification
+        .status
+        .as_ref()
+        .map(|s| NotificationTarget::Status(s.id.clone()));
+    let status_body = notification
+        .status
+        .as_ref()
+        .map(summarise)
+        .unwrap_or_default();
+
+    match notification.r#type {
+        NotificationType::Mention => Some((
+            format!("{} mentioned you", name),
+            status_body,
+            status_target.unwrap_or(account_target),
+        )),
+        NotificationType::Follow => Some((
+            format!("{} followed you", name),
+            format!("@{}", account.acct),
+            account_target,
+        )),
+        NotificationType::FollowRequest => Some((
+            format!("{} requested to follow you", name),
+            format!("@{}", account.acct),
+            account_target,
+        )),
+        NotificationType::Favourite => Some((
+            format!("{} favourited your post", name),
+            status_body,
+            status_target.unwrap_or(account_target),
+        )),
+        NotificationType::Reblog => Some((
+            format!("{} boosted your post", name),
+            status_body,
+            status_target.unwrap_or(account_target),
+        )),
+        _ => None,
+    }
+}
+
+fn summarise(status: &entities::Status) -> String {
+    if !status.spoiler_text.is_empty() {
+        return format!("CW: {}", status.spoiler_text);
+    }
+
+    if let Some(plain) = &status.plain_content {
+        return plain.clone();
+    }
+
+    text::strip_html(&status.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let quiet = QuietHours {
+            start_hour: 9,
+            end_hour: 17,
+        };
+        assert!(!quiet.contains(8));
+        assert!(quiet.contains(9));
+        assert!(quiet.contains(16));
+        assert!(!quiet.contains(17));
+    }
+
+    #[test]
+    fn quiet_hours_past_midnight() {
+        let quiet = QuietHours {
+            start_hour: 22,
+            end_hour: 7,
+        };
+        assert!(quiet.contains(22));
+        assert!(quiet.contains(23));
+        assert!(quiet.contains(0));
+        assert!(quiet.contains(6));
+        assert!(!quiet.contains(7));
+        assert!(!quiet.contains(12));
+        assert!(!quiet.contains(21));
+    }
+
+    #[test]
+    fn empty_quiet_hours_never_apply() {
+        let quiet = QuietHours {
+            start_hour: 5,
+            end_hour: 5,
+        };
+        assert!((0..24).all(|hour| !quiet.contains(hour)));
+    }
+
+    #[test]
+    fn limits_notifications_per_minute() {
+        let notifier = Notifier::default();
+        assert!(notifier.try_acquire(2));
+        assert!(notifier.try_acquire(2));
+        assert!(!notifier.try_acquire(2));
+        // A higher limit lets more through from the same window
+        assert!(notifier.try_acquire(3));
+    }
+
+    #[test]
+    fn frees_up_after_a_minute() {
+        let notifier = Notifier::default();
+        notifier
+            .recent
+            .lock()
+            .push_back(Instant::now() - Duration::from_secs(61));
+        assert!(notifier.try_acquire(1));
+        assert!(!notifier.try_acquire(1));
+    }
+}
@@ -0,0 +1,388 @@
+//! Writes that could not reach the instance.
+//!
+//! When a post or interaction fails because we are offline or the instance is having a bad
+//! moment, it is kept in `outbox.json` and sent again later, oldest first. Posts carry an
+//! idempotency key from their first attempt, so one that did reach the instance before the
+//! connection dropped is not posted twice.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use megalodon::entities;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::Notify;
+
+use crate::auth;
+use crate::config;
+use crate::error;
+use crate::request;
+use crate::settings;
+use crate::state::AppState;
+use crate::status;
+
+/// How often to try the outbox again while it has something in it
+const REPLAY_INTERVAL: Duration = Duration::from_secs(20);
+
+/// A write we can send again later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Post {
+        status: status::Content,
+        in_reply_to: Option<String>,
+        idempotency_key: String,
+    },
+    Favourite {
+        id: String,
+    },
+    Boost {
+        id: String,
+    },
+    Bookmark {
+        id: String,
+    },
+    Unbookmark {
+        id: String,
+    },
+}
+
+impl Action {
+    fn scope(&self) -> &'static str {
+        match self {
+            Action::Post { .. } | Action::Boost { .. } => "write:statuses",
+            Action::Favourite { .. } => "write:favourites",
+            Action::Bookmark { .. } | Action::Unbookmark { .. } => "write:bookmarks",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum EntryState {
+    /// Waiting for the next replay
+    Pending,
+    Sending,
+    /// The instance refused it, it stays here until retried or discarded
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: String,
+    pub action: Action,
+    /// `user@instance` it was made as. Entries from before this was required have none, and
+    /// are never sent.
+    pub account: Option<String>,
+    pub queued_at: DateTime<Utc>,
+    pub attempts: u32,
+    #[serde(flatten)]
+    pub state: EntryState,
+}
+
+#[derive(Default)]
+pub struct Outbox {
+    entries: Mutex<Vec<Entry>>,
+    /// Wakes the replay loop early
+    wake: Notify,
+}
+
+impl Entry {
+    /// Never send something as a different account than the one that made it
+    fn belongs_to(&self, account: &Option<String>) -> bool {
+        self.account.is_some() && self.account == *account
+    }
+}
+
+impl Outbox {
+    /// Whether anything made as `account` is still waiting to go out
+    fn has_pending(&self, account: &Option<String>) -> bool {
+        self.entries
+            .lock()
+            .iter()
+            .any(|e| !matches!(e.state, EntryState::Failed { .. }) && e.belongs_to(account))
+    }
+
+    fn set_state(&self, id: &str, state: EntryState) {
+        if let Some(entry) = self.entries.lock().iter_mut().find(|e| e.id == id) {
+            entry.state = state;
+        }
+    }
+}
+
+/// Sent when an entry from the outbox finally goes through
+#[derive(Debug, Clone, Serialize)]
+struct Sent {
+    id: String,
+    status: entities::Status,
+}
+
+pub fn load_outbox(state: &AppState) {
+    if let Some(mut entries) = config::load_or_report::<Vec<Entry>>(state, "outbox.json") {
+        for entry in &mut entries {
+            // We quit while these were in flight, the idempotency key covers posts that made it
+            if let EntryState::Sending = entry.state {
+                entry.state = EntryState::Pending;
+            }
+
+            if entry.account.is_none() {
+                entry.state = EntryState::Failed {
+                    error: "queued before its account was known, retrying sends it as the \
+                            current account"
+                        .to_string(),
+                };
+            }
+        }
+
+        *state.outbox.entries.lock() = entries;
+    }
+}
+
+fn save_outbox(state: &AppState) -> Result<(), error::DakkoError> {
+    let config_dir = state.config_dir.read();
+    config::save(
+        config_dir.as_ref().unwrap(),
+        "outbox.json",
+        &*state.outbox.entries.lock(),
+    )
+}
+
+/// Persist the outbox and tell the frontend what is in it
+fn changed(state: &AppState, handle: &tauri::AppHandle) {
+    if let Err(err) = save_outbox(state) {
+        eprintln!("Could not save the outbox: {}", err);
+    }
+
+    if let Err(err) = handle.emit_all("outbox-changed", &*state.outbox.entries.lock()) {
+        eprintln!("Could not send the outbox to the frontend: {}", err);
+    }
+}
+
+/// Send one write to the instance
+pub async fn perform(
+    state: &AppState,
+    action: &Action,
+) -> Result<entities::Status, error::DakkoError> {
+    if let Action::Post {
+        status,
+        in_reply_to,
+        idempotency_key,
+    } = action
+    {
+        return status::send_post(state, status, in_reply_to.as_deref(), idempotency_key).await;
+    }
+
+    let client = state.client.read_recursive();
+    let client = client.as_ref().unwrap();
+
+    let res = match action {
+        Action::Post { .. } => unreachable!(),
+        Action::Favourite { id } => {
+            request::write(&state.rate_limit, || client.favourite_status(id.clone())).await?
+        }
+        Action::Boost { id } => {
+            request::write(&state.rate_limit, || client.reblog_status(id.clone())).await?
+        }
+        Action::Bookmark { id } => {
+            request::write(&state.rate_limit, || client.bookmark_status(id.clone())).await?
+        }
+        Action::Unbookmark { id } => {
+            request::write(&state.rate_limit, || client.unbookmark_status(id.clone())).await?
+        }
+    };
+
+    Ok(res.json())
+}
+
+fn enqueue(
+    state: &AppState,
+    handle: &tauri::AppHandle,
+    action: Action,
+    account: String,
+    reason: &error::DakkoError,
+) -> error::DakkoError {
+    let id = auth::random_token(16);
+    state.outbox.entries.lock().push(Entry {
+        id: id.clone(),
+        action,
+        account: Some(account),
+        queued_at: Utc::now(),
+        attempts: 1,
+        state: EntryState::Pending,
+    });
+
+    eprintln!("Queued {} for later: {}", id, reason);
+    changed(state, handle);
+
+    error::DakkoError::Queued(id)
+}
+
+/// Send a write now, or queue it if the instance can't be reached. Anything already waiting
+/// in the outbox goes first, so writes reach the instance in the order they were made.
+pub async fn send(
+    state: &AppState,
+    handle: &tauri::AppHandle,
+    action: Action,
+) -> Result<entities::Status, error::DakkoError> {
+    // Without knowing who made it, a queued write could later go out as someone else
+    let account = match settings::account_key(state).await {
+        Some(account) => account,
+        None => return perform(state, &action).await,
+    };
+
+    if state.outbox.has_pending(&Some(account.clone())) {
+        let err = error::DakkoError::Invalid("earlier writes are still queued".to_string());
+        let queued = enqueue(state, handle, action, account, &err);
+        state.outbox.wake.notify_one();
+        return Err(queued);
+    }
+
+    match perform(state, &action).await {
+        Err(err) if request::is_transient(&err) => {
+            Err(enqueue(state, handle, action, account, &err))
+        }
+        res => res,
+    }
+}
+
+/// Send what is waiting, oldest first. Stops at the first entry that still can't get
+/// through, so nothing overtakes it.
+async fn replay(handle: &tauri::AppHandle) {
+    let state = handle.state::<AppState>();
+    if !state.has_logged_in() {
+        return;
+    }
+
+    let account = settings::account_key(&state).await;
+
+    loop {
+        let next = state
+            .outbox
+            .entries
+            .lock()
+            .iter()
+            .find(|e| matches!(e.state, EntryState::Pending) && e.belongs_to(&account))
+            .cloned();
+
+        let entry = match next {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        if let Err(err) = auth::require_scope(&state, entry.action.scope()) {
+            state.outbox.set_state(
+                &entry.id,
+                EntryState::Failed {
+                    error: err.to_string(),
+                },
+            );
+            changed(&state, handle);
+            continue;
+        }
+
+        state.outbox.set_state(&entry.id, EntryState::Sending);
+        let result = perform(&state, &entry.action).await;
+
+        {
+            let mut entries = state.outbox.entries.lock();
+            // Gone if it was discarded while we were sending it
+            if let Some(index) = entries.iter().position(|e| e.id == entry.id) {
+                match &result {
+                    Ok(_) => {
+                        entries.remove(index);
+                    }
+                    Err(err) if request::is_transient(err) => {
+                        entries[index].attempts += 1;
+                        entries[index].state = EntryState::Pending;
+                    }
+                    Err(err) => {
+                        entries[index].attempts += 1;
+                        entries[index].state = EntryState::Failed {
+                            error: err.to_string(),
+                        };
+                    }
+                }
+            }
+        }
+
+        changed(&state, handle);
+
+        match result {
+            Ok(status) => {
+                let sent = Sent {
+                    id: entry.id,
+                    status,
+                };
+                if let Err(err) = handle.emit_all("outbox-sent", sent) {
+                    eprintln!("Could not tell the frontend a write was sent: {}", err);
+                }
+            }
+            Err(err) if request::is_transient(&err) => return,
+            Err(_) => {}
+        }
+    }
+}
+
+pub async fn run(handle: tauri::AppHandle) {
+    loop {
+        let state = handle.state::<AppState>();
+        tokio::select! {
+            _ = tokio::time::sleep(REPLAY_INTERVAL) => {}
+            _ = state.outbox.wake.notified() => {}
+        }
+
+        if !state.outbox.entries.lock().is_empty() {
+            replay(&handle).await;
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_outbox(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Entry>, error::DakkoError> {
+    Ok(state.outbox.entries.lock().clone())
+}
+
+/// Try a failed entry again, or everything pending if no id is given
+#[tauri::command]
+pub async fn retry_outbox(
+    id: Option<String>,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<(), error::DakkoError> {
+    if let Some(id) = id {
+        let account = settings::account_key(&state).await;
+        let mut entries = state.outbox.entries.lock();
+        let entry = entries
+            .iter_mut()
+            .find(|e| e.id == id)
+            .ok_or_else(|| error::DakkoError::Invalid(format!("no queued write {}", id)))?;
+
+        if let EntryState::Failed { .. } = entry.state {
+            entry.state = EntryState::Pending;
+        }
+        // Asking to retry one that was made before we knew the account is what claims it
+        if entry.account.is_none() {
+            entry.account = account;
+        }
+    }
+
+    changed(&state, &handle);
+    state.outbox.wake.notify_one();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn discard_outbox_entry(
+    id: String,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<(), error::DakkoError> {
+    state.outbox.entries.lock().retain(|e| e.id != id);
+    changed(&state, &handle);
+    Ok(())
+}
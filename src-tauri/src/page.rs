@@ -0,0 +1,48 @@
+use reqwest::header::{HeaderMap, LINK};
+use serde::Serialize;
+
+/// One page of a paginated listing. `next` and `prev` are the ids to pass back as
+/// `max_id` / `since_id` to continue in either direction.
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Build a page from the `Link` header the server sent. Account listings are paginated by
+    /// follow/block ids rather than account ids, so the header is the only reliable cursor.
+    pub fn from_headers(items: Vec<T>, headers: &HeaderMap) -> Self {
+        let mut next = None;
+        let mut prev = None;
+
+        let link = headers.get(LINK).and_then(|l| l.to_str().ok());
+        for part in link.unwrap_or_default().split(',') {
+            let (url, rel) = match part.split_once(';') {
+                Some(split) => split,
+                None => continue,
+            };
+
+            let url = url.trim().trim_start_matches('<').trim_end_matches('>');
+            let url = match reqwest::Url::parse(url) {
+                Ok(url) => url,
+                Err(_) => continue,
+            };
+
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.to_string())
+            };
+
+            if rel.contains("\"next\"") {
+                next = param("max_id");
+            } else if rel.contains("\"prev\"") {
+                prev = param("since_id").or_else(|| param("min_id"));
+            }
+        }
+
+        Self { items, next, prev }
+    }
+}
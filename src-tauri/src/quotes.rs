@@ -0,0 +1,224 @@
+//! Quote posts.
+//!
+//! Servers disagree on how to make one. Pleroma, Akkoma and Fedibird take a `quote_id`,
+//! Mastodon from 4.5 takes a `quoted_status_id`, and everything else has no notion of
+//! quotes at all. There we put the quoted post's link behind an `RE:` line, which is also
+//! how the servers that do support quotes render them for everyone else, so reading quotes
+//! back out of timelines works the same way for all of them.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use lazy_static::lazy_static;
+use megalodon::entities;
+use parking_lot::{Mutex, RwLock};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::capabilities;
+use crate::error;
+use crate::http::HttpClient;
+use crate::request;
+use crate::server_filters::FilteredStatus;
+use crate::state::AppState;
+
+/// How long a looked up quote is kept before asking the instance again. Posts we could not
+/// find are remembered as long, rather than searched for on every page.
+const TTL: Duration = Duration::from_secs(10 * 60);
+/// The `RE:` line a quote leaves in the content, whether linked or bare
+const QUOTE_LINE: &str = r#"(?i)\b(?:RE|QT):\s*(?:<a [^>]*?href="([^"]+)"|(https?://[^\s<"]+))"#;
+
+lazy_static! {
+    static ref QUOTE_PATTERN: Regex = Regex::new(QUOTE_LINE).unwrap();
+}
+
+/// How the instance wants to be told a post is a quote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuoteSupport {
+    /// Pleroma, Akkoma and Fedibird
+    QuoteId,
+    /// Mastodon 4.5 and later
+    QuotedStatusId,
+    /// Quotes are written into the text instead
+    Link,
+}
+
+/// The post a status quotes
+#[derive(Debug, Clone, Serialize)]
+pub struct Quote {
+    pub url: String,
+    /// Left out if our instance could not fetch it, or it is not visible to us
+    pub status: Option<Box<entities::Status>>,
+}
+
+#[derive(Default)]
+pub struct QuoteState {
+    support: RwLock<Option<QuoteSupport>>,
+    /// Quoted posts by url
+    resolved: Mutex<HashMap<String, (Instant, Option<entities::Status>)>>,
+}
+
+impl QuoteState {
+    /// Forget what we know, for when the instance changes
+    pub fn reset(&self) {
+        *self.support.write() = None;
+        self.resolved.lock().clear();
+    }
+}
+
+async fn detect(state: &AppState) -> Result<QuoteSupport, error::DakkoError> {
+    let v1 = capabilities::instance(state).await?;
+    let pleroma_quotes = v1["pleroma"]["metadata"]["features"]
+        .as_array()
+        .map_or(false, |features| {
+            features.iter().any(|f| f == "quote_posting")
+        });
+    if pleroma_quotes || v1["feature_quote"].as_bool() == Some(true) {
+        return Ok(QuoteSupport::QuoteId);
+    }
+
+    // Older and non-Mastodon servers may not have the v2 endpoint at all
+    let http = HttpClient::from_state(state);
+    if let Ok(v2) = http.get::<serde_json::Value>("/api/v2/instance", &[]).await {
+        if v2["api_versions"]["mastodon"]
+            .as_u64()
+            .map_or(false, |v| v >= 7)
+        {
+            return Ok(QuoteSupport::QuotedStatusId);
+        }
+    }
+
+    Ok(QuoteSupport::Link)
+}
+
+/// How quotes are made on the logged in instance, worked out the first time it is needed
+pub async fn support(state: &AppState) -> Result<QuoteSupport, error::DakkoError> {
+    if let Some(support) = *state.quotes.support.read() {
+        return Ok(support);
+    }
+
+    let support = detect(state).await?;
+    *state.quotes.support.write() = Some(support);
+    Ok(support)
+}
+
+/// `content` with a link to `quoting` appended, for instances without native quotes
+pub async fn with_link(
+    state: &AppState,
+    content: &str,
+    quoting: &str,
+) -> Result<String, error::DakkoError> {
+    let quoted = {
+        let client = state.client.read_recursive();
+        let client = client.as_ref().unwrap();
+
+        request::read(&state.rate_limit, || client.get_status(quoting.to_string()))
+            .await?
+            .json()
+    };
+    let url = quoted.url.unwrap_or(quoted.uri);
+
+    if content.contains(&url) {
+        return Ok(content.to_string());
+    }
+
+    Ok(format!("{}\n\nRE: {}", content.trim_end(), url))
+}
+
+/// The link a status' content quotes, if any
+fn quoted_url(status: &entities::Status) -> Option<String> {
+    let captures = QUOTE_PATTERN.captures(&status.content)?;
+    captures
+        .get(1)
+        .or_else(|| captures.get(2))
+        .map(|url| url.as_str().replace("&amp;", "&"))
+}
+
+async fn lookup(
+    state: &AppState,
+    url: &str,
+) -> Result<Option<entities::Status>, error::DakkoError> {
+    let status = {
+        // Timelines hold a read lock on the client while they are prepared
+        let client = state.client.read_recursive();
+        let client = client.as_ref().unwrap();
+
+        // A resolving search fetches the post onto our instance if it has not seen it yet
+        let options = megalodon::megalodon::SearchInputOptions {
+            resolve: Some(true),
+            limit: Some(1),
+            ..Default::default()
+        };
+
+        request::read(&state.rate_limit, || {
+            client.search(url.to_string(), Some(&options))
+        })
+        .await?
+        .json()
+        .statuses
+        .into_iter()
+        .next()
+    };
+
+    Ok(status)
+}
+
+async fn resolve(state: &AppState, url: &str) -> Option<entities::Status> {
+    if let Some((at, status)) = state.quotes.resolved.lock().get(url) {
+        if at.elapsed() < TTL {
+            return status.clone();
+        }
+    }
+
+    let status = match lookup(state, url).await {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("Could not look up quoted post {}: {}", url, err);
+            None
+        }
+    };
+
+    state
+        .quotes
+        .resolved
+        .lock()
+        .insert(url.to_string(), (Instant::now(), status.clone()));
+    status
+}
+
+/// Fill in the `quoted` of statuses that quote another. A quote we cannot look up is still
+/// given, without the status, so it can be shown as a link.
+pub async fn attach(state: &AppState, statuses: &mut [FilteredStatus]) {
+    let mut wanted = Vec::new();
+    for (index, filtered) in statuses.iter().enumerate() {
+        // A boost shows the boosted post, which may be the one quoting
+        let shown = filtered
+            .status
+            .reblog
+            .as_deref()
+            .unwrap_or(&filtered.status);
+        if let Some(url) = quoted_url(shown) {
+            wanted.push((index, url));
+        }
+    }
+
+    let resolved = join_all(wanted.iter().map(|(_, url)| resolve(state, url))).await;
+    for ((index, url), status) in wanted.into_iter().zip(resolved) {
+        statuses[index].quoted = Some(Quote {
+            url,
+            status: status.map(Box::new),
+        });
+    }
+}
+
+#[tauri::command]
+pub async fn get_quote_support(
+    state: tauri::State<'_, AppState>,
+) -> Result<QuoteSupport, error::DakkoError> {
+    assert!(state.has_logged_in());
+    support(&state).await
+}
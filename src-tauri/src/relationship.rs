@@ -1,29 +1,163 @@
-use megalodon::{entities, megalodon::FollowRequestOutput};
+use std::time::Duration;
 
-use crate::state::AppState;
+use megalodon::entities;
+use serde::{Deserialize, Deserializer, Serialize};
+use tauri::Manager;
+
+use crate::auth;
 use crate::error;
+use crate::filter;
+use crate::http::HttpClient;
+use crate::page::Page;
+use crate::request;
+use crate::state::AppState;
+
+/// Servers disagree on whether a follow request is an account or its own entity (with a
+/// numeric id), so both are flattened into this.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowRequest {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub username: String,
+    pub acct: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub note: String,
+    pub url: String,
+    pub avatar: String,
+    #[serde(default)]
+    pub header: String,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default)]
+    pub bot: bool,
+    #[serde(default)]
+    pub emojis: Vec<entities::Emoji>,
+}
 
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Str(String),
+        Num(u64),
+    }
+
+    Ok(match Id::deserialize(deserializer)? {
+        Id::Str(s) => s,
+        Id::Num(n) => n.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BulkResult {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
 
 #[tauri::command]
 pub async fn get_follow_requests(
+    limit: Option<u32>,
+    max_id: Option<String>,
     state: tauri::State<'_, AppState>,
-) -> Result<Vec<entities::Account>, error::DakkoError> {
+) -> Result<Page<FollowRequest>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:follows")?;
 
-    let client = state.client.read();
-    let client = client.as_ref().unwrap();
+    // megalodon can only fetch the first page, so every page comes straight from the server
+    // and carries its cursor in the Link header
+    let mut query = vec![("limit", limit.unwrap_or(40).to_string())];
+    if let Some(max_id) = max_id {
+        query.push(("max_id", max_id));
+    }
 
-    let res = client.get_follow_requests(None).await?;
-    let requests = res.json();
-    let requests = requests
-        .into_iter()
-        .map(|r| match r {
-            FollowRequestOutput::Account(d) => d,
-            FollowRequestOutput::FollowRequest(_) => todo!("implement this type"),
-        })
-        .collect();
+    let res = HttpClient::from_state(&state)
+        .send::<()>(reqwest::Method::GET, "/api/v1/follow_requests", &query, None)
+        .await?;
+    let headers = res.headers().clone();
+    let requests: Vec<FollowRequest> = res.json().await?;
+
+    Ok(Page::from_headers(requests, &headers))
+}
+
+async fn resolve_follow_requests(
+    ids: Vec<String>,
+    accept: bool,
+    state: &tauri::State<'_, AppState>,
+    handle: &tauri::AppHandle,
+) -> BulkResult {
+    let total = ids.len();
+    let mut result = BulkResult::default();
+
+    for (done, id) in ids.into_iter().enumerate() {
+        if done > 0 {
+            // Be gentle, a large backlog would otherwise burn through the rate limit
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+
+        // Taken per request, so a long backlog doesn't keep logins and instance switches waiting
+        let res = {
+            let client = state.client.read();
+            let client = client.as_ref().unwrap();
+
+            if accept {
+                request::write(&state.rate_limit, || client.accept_follow_request(id.clone())).await
+            } else {
+                request::write(&state.rate_limit, || client.reject_follow_request(id.clone())).await
+            }
+        };
+
+        match res {
+            Ok(res) => {
+                state.relationships.store(&res.json);
+                result.succeeded.push(id)
+            }
+            Err(err) => result.failed.push((id, err.to_string())),
+        }
+
+        handle
+            .emit_all(
+                "follow-requests-progress",
+                BulkProgress {
+                    done: done + 1,
+                    total,
+                },
+            )
+            .unwrap();
+    }
+
+    result
+}
 
-    Ok(requests)
+#[tauri::command]
+pub async fn accept_follow_requests(
+    ids: Vec<String>,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<BulkResult, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:follows")?;
+
+    Ok(resolve_follow_requests(ids, true, &state, &handle).await)
+}
+
+#[tauri::command]
+pub async fn deny_follow_requests(
+    ids: Vec<String>,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<BulkResult, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:follows")?;
+
+    Ok(resolve_follow_requests(ids, false, &state, &handle).await)
 }
 
 #[tauri::command]
@@ -32,14 +166,9 @@ pub async fn get_relationships(
     state: tauri::State<'_, AppState>,
 ) -> Result<Vec<entities::Relationship>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:follows")?;
 
-    let client = state.client.read();
-    let client = client.as_ref().unwrap();
-
-    let res = client
-        .get_relationships(account_ids)
-        .await?;
-    Ok(res.json())
+    state.relationships.get(&state, account_ids).await
 }
 
 #[tauri::command]
@@ -48,12 +177,15 @@ pub async fn accept_follow_request(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:follows")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.accept_follow_request(id).await?;
-    Ok(res.json())
+    let res = request::write(&state.rate_limit, || client.accept_follow_request(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
 }
 
 #[tauri::command]
@@ -62,26 +194,63 @@ pub async fn deny_follow_request(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:follows")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.reject_follow_request(id).await?;
-    Ok(res.json())
+    let res = request::write(&state.rate_limit, || client.reject_follow_request(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
 }
 
+#[derive(Serialize)]
+struct FollowBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reblogs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notify: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    languages: Option<Vec<String>>,
+}
+
+/// Follow an account. Left out, whether their boosts are shown, whether their posts notify
+/// us and which of their languages we see stay as the server defaults them.
 #[tauri::command]
 pub async fn follow_user(
     id: String,
+    reblogs: Option<bool>,
+    notify: Option<bool>,
+    languages: Option<Vec<String>>,
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:follows")?;
+
+    // megalodon can't pass languages, so follows with options go to the API directly
+    if reblogs.is_some() || notify.is_some() || languages.is_some() {
+        let body = FollowBody {
+            reblogs,
+            notify,
+            languages,
+        };
+
+        let relationship: entities::Relationship = HttpClient::from_state(&state)
+            .post(&format!("/api/v1/accounts/{}/follow", id), &body)
+            .await?;
+        state.relationships.store(&relationship);
+
+        return Ok(relationship);
+    }
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.follow_account(id, None).await?;
-    Ok(res.json())
+    let res = request::write(&state.rate_limit, || client.follow_account(id.clone(), None)).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
 }
 
 #[tauri::command]
@@ -90,12 +259,15 @@ pub async fn unfollow_user(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:follows")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.unfollow_account(id).await?;
-    Ok(res.json())
+    let res = request::write(&state.rate_limit, || client.unfollow_account(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
 }
 
 #[tauri::command]
@@ -104,12 +276,15 @@ pub async fn block_user(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:blocks")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.block_account(id).await?;
-    Ok(res.json())
+    let res = request::write(&state.rate_limit, || client.block_account(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
 }
 
 #[tauri::command]
@@ -118,26 +293,186 @@ pub async fn unblock_user(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:blocks")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let res = request::write(&state.rate_limit, || client.unblock_account(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
+}
+
+/// Set the private note shown only to us on an account, an empty note clears it
+#[tauri::command]
+pub async fn set_account_note(
+    id: String,
+    note: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<entities::Relationship, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:accounts")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let note = note.filter(|n| !n.trim().is_empty());
+    let res = request::write(&state.rate_limit, || {
+        client.set_account_note(id.clone(), note.clone())
+    })
+    .await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
+}
+
+/// Feature an account on our profile
+#[tauri::command]
+pub async fn endorse_account(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<entities::Relationship, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:accounts")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let res = request::write(&state.rate_limit, || client.pin_account(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
+}
+
+#[tauri::command]
+pub async fn unendorse_account(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<entities::Relationship, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:accounts")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let res = request::write(&state.rate_limit, || client.unpin_account(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
+}
+
+#[tauri::command]
+pub async fn get_endorsements(
+    limit: Option<u32>,
+    max_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Page<entities::Account>, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:accounts")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.unblock_account(id).await?;
-    Ok(res.json())
+    let options = megalodon::megalodon::GetEndorsementsInputOptions {
+        limit: Some(limit.unwrap_or(40)),
+        max_id,
+        ..Default::default()
+    };
+
+    let res = request::read(&state.rate_limit, || client.get_endorsements(Some(&options))).await?;
+    Ok(Page::from_headers(res.json, &res.header))
 }
 
+#[derive(Serialize)]
+struct MuteBody {
+    notifications: bool,
+    duration: u64,
+}
+
+/// Mute an account, optionally hiding their notifications too. `duration` is in seconds,
+/// leaving it out mutes indefinitely.
 #[tauri::command]
 pub async fn mute_user(
     id: String,
+    notifications: Option<bool>,
+    duration: Option<u64>,
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:mutes")?;
+
+    let notifications = notifications.unwrap_or(false);
+
+    // megalodon has no way to pass a duration, so timed mutes go to the API directly
+    if let Some(duration) = duration {
+        let body = MuteBody {
+            notifications,
+            duration,
+        };
+
+        let relationship: entities::Relationship = HttpClient::from_state(&state)
+            .post(&format!("/api/v1/accounts/{}/mute", id), &body)
+            .await?;
+        state.relationships.store(&relationship);
+
+        return Ok(relationship);
+    }
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let res = request::write(&state.rate_limit, || {
+        client.mute_account(id.clone(), notifications)
+    })
+    .await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
+}
+
+#[tauri::command]
+pub async fn get_mutes(
+    limit: Option<u32>,
+    max_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Page<entities::Account>, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:mutes")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.mute_account(id, false).await?;
-    Ok(res.json())
+    let options = megalodon::megalodon::GetMutesInputOptions {
+        limit: Some(limit.unwrap_or(40)),
+        max_id,
+        ..Default::default()
+    };
+
+    let res = request::read(&state.rate_limit, || client.get_mutes(Some(&options))).await?;
+    Ok(Page::from_headers(res.json, &res.header))
+}
+
+#[tauri::command]
+pub async fn get_blocks(
+    limit: Option<u32>,
+    max_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Page<entities::Account>, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:blocks")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let options = megalodon::megalodon::GetBlocksInputOptions {
+        limit: Some(limit.unwrap_or(40)),
+        max_id,
+        ..Default::default()
+    };
+
+    let res = request::read(&state.rate_limit, || client.get_blocks(Some(&options))).await?;
+    Ok(Page::from_headers(res.json, &res.header))
 }
 
 #[tauri::command]
@@ -146,10 +481,164 @@ pub async fn unmute_user(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Relationship, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:mutes")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let res = request::write(&state.rate_limit, || client.unmute_account(id.clone())).await?;
+    let relationship = res.json();
+    state.relationships.store(&relationship);
+    Ok(relationship)
+}
+
+/// Every domain the account blocks, following the listing's pages to the end
+async fn fetch_domain_blocks(state: &AppState) -> Result<Vec<String>, error::DakkoError> {
+    let mut domains = vec![];
+    let mut max_id = None;
+
+    loop {
+        let options = megalodon::megalodon::GetDomainBlocksInputOptions {
+            limit: Some(200),
+            max_id,
+            ..Default::default()
+        };
+
+        let page = {
+            // Callers are usually timeline commands already holding a read lock on the client
+            let client = state.client.read_recursive();
+            let client = client.as_ref().unwrap();
+
+            let res = request::read(&state.rate_limit, || client.get_domain_blocks(Some(&options))).await?;
+            Page::from_headers(res.json, &res.header)
+        };
+
+        let exhausted = page.items.is_empty();
+        domains.extend(page.items.into_iter().map(|d| d.to_lowercase()));
+        max_id = match page.next {
+            Some(next) if !exhausted => Some(next),
+            _ => return Ok(domains),
+        };
+    }
+}
+
+async fn cached_domain_blocks(state: &AppState) -> Vec<String> {
+    if let Some(domains) = state.domain_blocks.read().as_ref() {
+        return domains.clone();
+    }
+
+    match fetch_domain_blocks(state).await {
+        Ok(domains) => {
+            *state.domain_blocks.write() = Some(domains.clone());
+            domains
+        }
+        Err(err) => {
+            // Not cached, the next timeline load tries again
+            eprintln!("Could not fetch domain blocks: {}", err);
+            vec![]
+        }
+    }
+}
+
+/// The server keeps blocked domains out of timelines, but boosts of their posts by people
+/// we follow still make it through. Drop those too.
+pub async fn without_blocked_domains(
+    state: &AppState,
+    statuses: Vec<entities::Status>,
+) -> Vec<entities::Status> {
+    let blocked = cached_domain_blocks(state).await;
+    if blocked.is_empty() {
+        return statuses;
+    }
+
+    let is_blocked = |account: &entities::Account| {
+        filter::domain_of(account).map_or(false, |domain| {
+            blocked
+                .iter()
+                .any(|b| domain == *b || domain.ends_with(&format!(".{}", b)))
+        })
+    };
+
+    statuses
+        .into_iter()
+        .filter(|s| {
+            let boosted_blocked = s.reblog.as_ref().map_or(false, |r| is_blocked(&r.account));
+            !is_blocked(&s.account) && !boosted_blocked
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub async fn get_domain_blocks(
+    limit: Option<u32>,
+    max_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Page<String>, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:blocks")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let options = megalodon::megalodon::GetDomainBlocksInputOptions {
+        limit: Some(limit.unwrap_or(100)),
+        max_id,
+        ..Default::default()
+    };
+
+    let res = request::read(&state.rate_limit, || client.get_domain_blocks(Some(&options))).await?;
+    Ok(Page::from_headers(res.json, &res.header))
+}
+
+#[tauri::command]
+pub async fn block_domain(
+    domain: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:blocks")?;
+
+    let domain = domain.trim().to_lowercase();
+    if domain.is_empty() {
+        return Err(error::DakkoError::Invalid("domain cannot be empty".to_string()));
+    }
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.unmute_account(id).await?;
-    Ok(res.json())
-}
\ No newline at end of file
+    request::write(&state.rate_limit, || client.block_domain(domain.clone())).await?;
+    // Flips `domain_blocking` on every relationship with that domain
+    state.relationships.invalidate_all();
+
+    if let Some(domains) = state.domain_blocks.write().as_mut() {
+        if !domains.contains(&domain) {
+            domains.push(domain);
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn unblock_domain(
+    domain: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:blocks")?;
+
+    let domain = domain.trim().to_lowercase();
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    request::write(&state.rate_limit, || client.unblock_domain(domain.clone())).await?;
+    // Flips `domain_blocking` on every relationship with that domain
+    state.relationships.invalidate_all();
+
+    if let Some(domains) = state.domain_blocks.write().as_mut() {
+        domains.retain(|d| *d != domain);
+    }
+
+    Ok(())
+}
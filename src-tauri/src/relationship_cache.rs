@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use megalodon::entities;
+use parking_lot::Mutex;
+use tokio::sync::watch;
+
+use crate::error;
+use crate::request;
+use crate::state::AppState;
+
+const TTL: Duration = Duration::from_secs(60);
+/// The most ids Mastodon will answer for in one request
+const BATCH_SIZE: usize = 40;
+
+/// Relationships keyed by account id. Timelines ask for the same handful of accounts over
+/// and over, so answers are kept for a short while, and callers asking for ids that are
+/// already being fetched wait for that request instead of sending their own.
+#[derive(Default)]
+pub struct RelationshipCache {
+    entries: Mutex<HashMap<String, (Instant, entities::Relationship)>>,
+    /// Ids currently being fetched. The sender is dropped once the fetch is done, which is
+    /// what waiters listen for.
+    in_flight: Mutex<HashMap<String, watch::Receiver<()>>>,
+}
+
+impl RelationshipCache {
+    pub fn store(&self, relationship: &entities::Relationship) {
+        self.entries
+            .lock()
+            .insert(relationship.id.clone(), (Instant::now(), relationship.clone()));
+    }
+
+    pub fn invalidate_all(&self) {
+        self.entries.lock().clear();
+    }
+
+    fn cached(&self, ids: &[String]) -> Vec<entities::Relationship> {
+        let entries = self.entries.lock();
+        ids.iter()
+            .filter_map(|id| entries.get(id))
+            .filter(|(at, _)| at.elapsed() < TTL)
+            .map(|(_, r)| r.clone())
+            .collect()
+    }
+
+    async fn fetch(&self, state: &AppState, ids: &[String]) -> Result<(), error::DakkoError> {
+        // Callers may already hold a read lock on the client
+        let client = state.client.read_recursive();
+        let client = client.as_ref().unwrap();
+
+        for chunk in ids.chunks(BATCH_SIZE) {
+            let res =
+                request::read(&state.rate_limit, || client.get_relationships(chunk.to_vec()))
+                    .await?;
+            for relationship in res.json() {
+                self.store(&relationship);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn get(
+        &self,
+        state: &AppState,
+        ids: Vec<String>,
+    ) -> Result<Vec<entities::Relationship>, error::DakkoError> {
+        let (tx, rx) = watch::channel(());
+        let mut waiting = vec![];
+        let mut to_fetch = vec![];
+
+        {
+            let entries = self.entries.lock();
+            let mut in_flight = self.in_flight.lock();
+
+            for id in &ids {
+                let fresh = entries.get(id).map_or(false, |(at, _)| at.elapsed() < TTL);
+                if fresh || to_fetch.contains(id) {
+                    continue;
+                }
+
+                match in_flight.get(id) {
+                    Some(other) => waiting.push(other.clone()),
+                    None => {
+                        in_flight.insert(id.clone(), rx.clone());
+                        to_fetch.push(id.clone());
+                    }
+                }
+            }
+        }
+
+        let fetched = if to_fetch.is_empty() {
+            Ok(())
+        } else {
+            self.fetch(state, &to_fetch).await
+        };
+
+        {
+            let mut in_flight = self.in_flight.lock();
+            for id in &to_fetch {
+                in_flight.remove(id);
+            }
+        }
+
+        // Wake anyone who piggybacked on our request, whether it worked or not
+        drop(tx);
+        fetched?;
+
+        for mut other in waiting {
+            // Errors here just mean the other request finished
+            let _ = other.changed().await;
+        }
+
+        // Anything still missing was part of someone else's request that failed, try it
+        // ourselves rather than returning a partial answer
+        let found = self.cached(&ids);
+        let missing: Vec<String> = ids
+            .iter()
+            .filter(|id| !found.iter().any(|r| r.id == **id))
+            .cloned()
+            .collect();
+
+        if !missing.is_empty() {
+            self.fetch(state, &missing).await?;
+        }
+
+        Ok(self.cached(&ids))
+    }
+}
@@ -0,0 +1,225 @@
+use std::{future::Future, time::Duration};
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+
+use crate::error;
+use crate::state::AppState;
+
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+/// How long a request will sit out a rate limit itself. Callers hold the client lock while
+/// it waits, which would stall logging in or switching instance, so anything longer is
+/// handed back to them as `RateLimited`.
+const MAX_RESET_WAIT: Duration = Duration::from_secs(10);
+/// Assumed when the server limits us without saying when it resets
+const DEFAULT_RESET: Duration = Duration::from_secs(60);
+
+/// What the server last told us about our rate limit
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RateBudget {
+    pub limit: Option<u32>,
+    pub remaining: Option<u32>,
+    pub reset: Option<DateTime<Utc>>,
+    /// Writes waiting for the limit to reset
+    pub queued_writes: usize,
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    budget: RwLock<RateBudget>,
+    /// Writes held back by a limit take turns on this, so they go out in the order made
+    writes: tokio::sync::Mutex<()>,
+}
+
+/// Anything a request returns that carries the response headers
+pub trait WithHeaders {
+    fn headers(&self) -> &HeaderMap;
+}
+
+impl<T> WithHeaders for megalodon::response::Response<T> {
+    fn headers(&self) -> &HeaderMap {
+        &self.header
+    }
+}
+
+impl WithHeaders for reqwest::Response {
+    fn headers(&self) -> &HeaderMap {
+        reqwest::Response::headers(self)
+    }
+}
+
+impl RateLimiter {
+    /// Update the budget from the `X-RateLimit-*` headers of a response
+    pub fn record(&self, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let mut budget = self.budget.write();
+
+        if let Some(limit) = header("x-ratelimit-limit").and_then(|v| v.parse().ok()) {
+            budget.limit = Some(limit);
+        }
+        if let Some(remaining) = header("x-ratelimit-remaining").and_then(|v| v.parse().ok()) {
+            budget.remaining = Some(remaining);
+        }
+        if let Some(reset) =
+            header("x-ratelimit-reset").and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+        {
+            budget.reset = Some(reset.with_timezone(&Utc));
+        }
+    }
+
+    /// The server answered 429, so nothing goes out until the limit resets
+    fn exhausted(&self) {
+        let mut budget = self.budget.write();
+        budget.remaining = Some(0);
+
+        let now = Utc::now();
+        if budget.reset.map_or(true, |reset| reset <= now) {
+            budget.reset = chrono::Duration::from_std(DEFAULT_RESET)
+                .ok()
+                .map(|wait| now + wait);
+        }
+    }
+
+    /// How long until requests can go out again, if we are out of budget
+    fn wait_needed(&self) -> Option<Duration> {
+        let budget = self.budget.read();
+        if budget.remaining != Some(0) {
+            return None;
+        }
+
+        (budget.reset? - Utc::now()).to_std().ok()
+    }
+
+    async fn sit_out_limit(&self) -> Result<(), error::DakkoError> {
+        if let Some(wait) = self.wait_needed() {
+            if wait > MAX_RESET_WAIT {
+                return Err(error::DakkoError::RateLimited(wait.as_secs() + 1));
+            }
+
+            tokio::time::sleep(wait).await;
+        }
+
+        Ok(())
+    }
+
+    pub fn budget(&self) -> RateBudget {
+        self.budget.read().clone()
+    }
+
+    /// Forget the budget of the previous instance or account
+    pub fn reset(&self) {
+        let mut budget = self.budget.write();
+        *budget = RateBudget {
+            queued_writes: budget.queued_writes,
+            ..Default::default()
+        };
+    }
+}
+
+/// Whether trying again could give a different answer
+pub fn is_transient(err: &error::DakkoError) -> bool {
+    let unanswered = |err: &reqwest::Error| err.is_connect() || err.is_timeout();
+
+    match (err.status(), err) {
+        (Some(status), _) => matches!(status, 429 | 500 | 502 | 503 | 504),
+        (None, error::DakkoError::RateLimited(_)) => true,
+        (None, error::DakkoError::Megalodon(megalodon::error::Error::RequestError(err))) => {
+            unanswered(err)
+        }
+        (None, error::DakkoError::Http(err)) => unanswered(err),
+        _ => false,
+    }
+}
+
+/// Exponential backoff, with jitter so retries from several commands do not line up
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_DELAY);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..1.0))
+}
+
+/// Send a request that is safe to repeat, retrying transient failures and waiting out rate
+/// limits. `call` is run once per attempt.
+pub async fn read<O, E, F, Fut>(limiter: &RateLimiter, call: F) -> Result<O, error::DakkoError>
+where
+    O: WithHeaders,
+    E: Into<error::DakkoError>,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<O, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        limiter.sit_out_limit().await?;
+
+        let err = match call().await {
+            Ok(res) => {
+                limiter.record(res.headers());
+                return Ok(res);
+            }
+            Err(err) => err.into(),
+        };
+
+        attempt += 1;
+        if attempt >= MAX_ATTEMPTS || !is_transient(&err) {
+            return Err(err);
+        }
+
+        if err.status() == Some(429) {
+            limiter.exhausted();
+        } else {
+            tokio::time::sleep(backoff(attempt - 1)).await;
+        }
+    }
+}
+
+/// Send a request that must not be repeated blindly. It is only tried again after a 429,
+/// which means the server did not act on it; while limited, writes queue up in order.
+pub async fn write<O, E, F, Fut>(limiter: &RateLimiter, call: F) -> Result<O, error::DakkoError>
+where
+    O: WithHeaders,
+    E: Into<error::DakkoError>,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<O, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        let _turn = if limiter.wait_needed().is_some() {
+            limiter.budget.write().queued_writes += 1;
+            let turn = limiter.writes.lock().await;
+            limiter.budget.write().queued_writes -= 1;
+
+            limiter.sit_out_limit().await?;
+            Some(turn)
+        } else {
+            None
+        };
+
+        let err = match call().await {
+            Ok(res) => {
+                limiter.record(res.headers());
+                return Ok(res);
+            }
+            Err(err) => err.into(),
+        };
+
+        attempt += 1;
+        if attempt >= MAX_ATTEMPTS || err.status() != Some(429) {
+            return Err(err);
+        }
+
+        limiter.exhausted();
+    }
+}
+
+#[tauri::command]
+pub async fn get_rate_limit(
+    state: tauri::State<'_, AppState>,
+) -> Result<RateBudget, error::DakkoError> {
+    Ok(state.rate_limit.budget())
+}
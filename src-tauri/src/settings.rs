@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::config;
+use crate::error;
+use crate::language;
+use crate::request;
+use crate::state::AppState;
+use crate::visibility::Visibility;
+
+const MIN_REFRESH_SECS: u64 = 10;
+const MAX_REFRESH_SECS: u64 = 60 * 60;
+
+/// What to do with statuses behind a content warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CwBehaviour {
+    /// Show the warning, with the content a click away
+    Collapse,
+    /// Show the warning and the content
+    Expand,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Replies are posted at least as restrictively as what they answer
+    pub default_visibility: Visibility,
+    pub cw_behaviour: CwBehaviour,
+    pub autoplay: bool,
+    pub refresh_interval_secs: u64,
+    /// Posted with when a post doesn't pick a language and none can be detected
+    pub default_language: Option<String>,
+    /// Languages to keep in timelines, everything if empty. Statuses in no particular
+    /// language are always kept.
+    pub read_languages: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            default_visibility: Visibility::public(),
+            cw_behaviour: CwBehaviour::Collapse,
+            autoplay: false,
+            refresh_interval_secs: 60,
+            default_language: None,
+            read_languages: vec![],
+        }
+    }
+}
+
+/// Settings one account changes from the global ones, unset fields fall through
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettingsOverride {
+    pub default_visibility: Option<Visibility>,
+    pub cw_behaviour: Option<CwBehaviour>,
+    pub autoplay: Option<bool>,
+    pub refresh_interval_secs: Option<u64>,
+    pub default_language: Option<String>,
+    pub read_languages: Option<Vec<String>>,
+}
+
+impl Settings {
+    fn validate(&self) -> Result<(), error::DakkoError> {
+        if !(MIN_REFRESH_SECS..=MAX_REFRESH_SECS).contains(&self.refresh_interval_secs) {
+            return Err(error::DakkoError::Invalid(format!(
+                "refresh interval must be between {} and {} seconds",
+                MIN_REFRESH_SECS, MAX_REFRESH_SECS
+            )));
+        }
+
+        Visibility::new(self.default_visibility.as_str())?;
+
+        let mut languages = self.default_language.iter().chain(&self.read_languages);
+        if let Some(bad) = languages.find(|code| !language::is_code(code)) {
+            return Err(error::DakkoError::Invalid(format!(
+                "{} is not a language code, use one like en or de",
+                bad
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn with(&self, over: &SettingsOverride) -> Settings {
+        Settings {
+            default_visibility: over
+                .default_visibility
+                .clone()
+                .unwrap_or_else(|| self.default_visibility.clone()),
+            cw_behaviour: over.cw_behaviour.unwrap_or(self.cw_behaviour),
+            autoplay: over.autoplay.unwrap_or(self.autoplay),
+            refresh_interval_secs: over
+                .refresh_interval_secs
+                .unwrap_or(self.refresh_interval_secs),
+            default_language: over
+                .default_language
+                .clone()
+                .or_else(|| self.default_language.clone()),
+            read_languages: over
+                .read_languages
+                .clone()
+                .unwrap_or_else(|| self.read_languages.clone()),
+        }
+    }
+}
+
+/// What `settings.json` holds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SettingsFile {
+    global: Settings,
+    /// Keyed by `user@instance`
+    accounts: HashMap<String, SettingsOverride>,
+}
+
+#[derive(Default)]
+pub struct SettingsStore {
+    file: RwLock<SettingsFile>,
+    /// `user@instance` of the logged in account, looked up the first time it is needed
+    account: RwLock<Option<String>>,
+}
+
+impl SettingsStore {
+    /// Forget which account is logged in, for when that changes
+    pub fn reset_account(&self) {
+        *self.account.write() = None;
+    }
+}
+
+/// Everything the settings screen needs: what applies now, and where it comes from
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsView {
+    pub effective: Settings,
+    pub global: Settings,
+    pub account: Option<String>,
+    pub overrides: SettingsOverride,
+}
+
+pub async fn account_key(state: &AppState) -> Option<String> {
+    if let Some(key) = state.settings.account.read().clone() {
+        return Some(key);
+    }
+
+    if !state.has_logged_in() {
+        return None;
+    }
+
+    let host = {
+        let client_state = state.client_state.read();
+        let base_url = &client_state.as_ref()?.base_url;
+        reqwest::Url::parse(base_url).ok()?.host_str()?.to_string()
+    };
+
+    let account = {
+        let client = state.client.read_recursive();
+        let client = client.as_ref().unwrap();
+
+        match request::read(&state.rate_limit, || client.verify_account_credentials()).await {
+            Ok(res) => res.json(),
+            Err(err) => {
+                eprintln!("Could not look up the account for settings: {}", err);
+                return None;
+            }
+        }
+    };
+
+    let key = format!("{}@{}", account.username, host);
+    *state.settings.account.write() = Some(key.clone());
+    Some(key)
+}
+
+fn view(state: &AppState, account: Option<String>) -> SettingsView {
+    let file = state.settings.file.read();
+    let overrides = account
+        .as_ref()
+        .and_then(|a| file.accounts.get(a))
+        .cloned()
+        .unwrap_or_default();
+
+    SettingsView {
+        effective: file.global.with(&overrides),
+        global: file.global.clone(),
+        account,
+        overrides,
+    }
+}
+
+/// The settings that apply to the logged in account
+pub async fn current(state: &AppState) -> Settings {
+    let account = account_key(state).await;
+    view(state, account).effective
+}
+
+/// The settings that apply to the logged in account, as far as we know without asking the
+/// instance. For places that can't wait on a request, or already hold the client lock.
+pub fn cached(state: &AppState) -> Settings {
+    let account = state.settings.account.read().clone();
+    view(state, account).effective
+}
+
+pub fn load_settings(state: &AppState) {
+    if let Some(file) = config::load_or_report::<SettingsFile>(state, "settings.json") {
+        *state.settings.file.write() = file;
+    }
+}
+
+fn save_settings(state: &AppState) -> Result<(), error::DakkoError> {
+    let config_dir = state.config_dir.read();
+    config::save(
+        config_dir.as_ref().unwrap(),
+        "settings.json",
+        &*state.settings.file.read(),
+    )
+}
+
+#[tauri::command]
+pub async fn get_settings(
+    state: tauri::State<'_, AppState>,
+) -> Result<SettingsView, error::DakkoError> {
+    let account = account_key(&state).await;
+    Ok(view(&state, account))
+}
+
+/// Replace the global settings, this account's overrides, or both
+#[tauri::command]
+pub async fn update_settings(
+    global: Option<Settings>,
+    overrides: Option<SettingsOverride>,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<SettingsView, error::DakkoError> {
+    let account = account_key(&state).await;
+    if overrides.is_some() && account.is_none() {
+        return Err(error::DakkoError::Invalid(
+            "log in to change settings for an account".to_string(),
+        ));
+    }
+
+    {
+        let mut file = state.settings.file.write();
+        let global = global.unwrap_or_else(|| file.global.clone());
+        global.validate()?;
+
+        if let (Some(account), Some(overrides)) = (&account, overrides) {
+            global.with(&overrides).validate()?;
+
+            if overrides == SettingsOverride::default() {
+                file.accounts.remove(account);
+            } else {
+                file.accounts.insert(account.clone(), overrides);
+            }
+        }
+
+        file.global = global;
+    }
+
+    save_settings(&state)?;
+
+    let view = view(&state, account);
+    handle.emit_all("settings-changed", &view).unwrap();
+
+    Ok(view)
+}
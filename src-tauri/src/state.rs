@@ -1,13 +1,29 @@
 use std::{
     net::SocketAddr,
     path::PathBuf,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use megalodon::{oauth::TokenData, Megalodon, SNS};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    auth::{self, PendingLogin},
+    capabilities::CapabilityCache,
+    drafts::DraftStore,
+    filter::FilterEngine,
+    notifier::Notifier,
+    outbox::Outbox,
+    quotes::QuoteState,
+    relationship_cache::RelationshipCache,
+    request::RateLimiter,
+    server_filters::CompiledFilter,
+    settings::SettingsStore,
+    status::RecentPosts,
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientState {
     pub client_id: String,
@@ -19,6 +35,14 @@ pub struct ClientState {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthState {
     pub token: TokenData,
+    /// What the token was granted. Logins from before scopes were configurable always asked
+    /// for the defaults.
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_scopes() -> Vec<String> {
+    auth::DEFAULT_SCOPES.map(String::from).to_vec()
 }
 pub struct AppState {
     pub client_state: RwLock<Option<ClientState>>,
@@ -26,7 +50,27 @@ pub struct AppState {
     pub client: RwLock<Option<Box<dyn Megalodon + Sync + Send>>>,
 
     pub redirect_addr: SocketAddr,
+    pub pending_login: Mutex<Option<PendingLogin>>,
+    /// A link the app was launched with, held until the frontend is ready for it
+    pub launch_link: Mutex<Option<String>>,
+    /// Problems loading the config dir, waiting to be shown to the user
+    pub config_errors: Mutex<Vec<String>>,
     pub config_dir: RwLock<Option<PathBuf>>,
+
+    pub notifier: Notifier,
+    pub http: reqwest::Client,
+    /// The server's filters, ready to match against statuses
+    pub server_filters: RwLock<Option<Arc<Vec<CompiledFilter>>>>,
+    pub filters: FilterEngine,
+    pub domain_blocks: RwLock<Option<Vec<String>>>,
+    pub relationships: RelationshipCache,
+    pub settings: SettingsStore,
+    pub rate_limit: Arc<RateLimiter>,
+    pub outbox: Outbox,
+    pub drafts: DraftStore,
+    pub recent_posts: RecentPosts,
+    pub quotes: QuoteState,
+    pub capabilities: CapabilityCache,
 }
 
 impl AppState {
@@ -1,15 +1,274 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
 use megalodon::entities;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
+use crate::auth;
+use crate::capabilities;
+use crate::http::HttpClient;
+use crate::language;
+use crate::outbox;
+use crate::quotes::{self, QuoteSupport};
+use crate::request;
+use crate::settings;
 use crate::state::AppState;
+use crate::visibility::Visibility;
 use crate::error;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Content {
     content: String,
     cw: Option<String>,
-    visibility: entities::status::StatusVisibility,
+    /// One of the instance's visibilities. Left out, the account's default is used, or for a
+    /// reply the parent's, if that shows it to fewer people.
+    #[serde(default)]
+    visibility: Option<Visibility>,
+    /// The visibility of the post being replied to, which the composer already has at hand
+    #[serde(default)]
+    in_reply_to_visibility: Option<Visibility>,
     quoting: Option<String>,
+    /// ISO 639-1 code. Left out, it is detected from the text, falling back to the account's
+    /// default language.
+    #[serde(default)]
+    language: Option<String>,
+    /// MIME type the content is written in, one of the instance's `post_formats`. Left out,
+    /// it is plain text.
+    #[serde(default)]
+    content_type: Option<String>,
+    /// Stays the same however many times the composer submits the post. Left out, every
+    /// submission counts as a new post.
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+/// How long a submitted post is remembered, so submitting it again gives back the original
+const COALESCE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+enum Submission {
+    /// Being posted right now. The sender is dropped once it is done, which is what
+    /// duplicate submissions wait for.
+    InFlight(watch::Receiver<()>),
+    Posted(Instant, entities::Status),
+    /// Waiting in the outbox under this id
+    Queued(Instant, String),
+}
+
+/// Posts submitted recently, by idempotency key
+#[derive(Default)]
+pub struct RecentPosts {
+    submissions: Mutex<HashMap<String, Submission>>,
+}
+
+/// Marks a submission as in flight for as long as it lives. Dropping it clears the mark,
+/// however the attempt ended, including a panic or the command being cancelled, and then
+/// wakes the duplicates waiting on it.
+struct InFlight<'a> {
+    submissions: &'a Mutex<HashMap<String, Submission>>,
+    key: String,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut submissions = self.submissions.lock();
+        if let Some(Submission::InFlight(_)) = submissions.get(&self.key) {
+            submissions.remove(&self.key);
+        }
+    }
+}
+
+/// What `POST /api/v1/statuses` takes. Posts go through here rather than megalodon so they
+/// can carry an idempotency key.
+#[derive(Serialize)]
+struct PostBody<'a> {
+    status: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    in_reply_to_id: Option<&'a str>,
+    sensitive: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spoiler_text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visibility: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quote_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quoted_status_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<&'a str>,
+}
+
+/// Post `content`, as a reply to `in_reply_to` if given. The instance ignores a second post
+/// with the same `idempotency_key`, answering with the first one instead.
+pub async fn send_post(
+    state: &AppState,
+    content: &Content,
+    in_reply_to: Option<&str>,
+    idempotency_key: &str,
+) -> Result<entities::Status, error::DakkoError> {
+    // Only missing from posts queued before it was filled in when the post was made
+    let visibility = match &content.visibility {
+        Some(visibility) => visibility.clone(),
+        None => default_visibility(state, content.in_reply_to_visibility.as_ref()).await,
+    };
+
+    let capabilities = capabilities::get(state).await?;
+    capabilities.check_visibility(&visibility)?;
+    if let Some(content_type) = &content.content_type {
+        capabilities.check_format(content_type)?;
+    }
+
+    let quoting = content.quoting.as_deref();
+    let support = match quoting {
+        Some(_) => Some(quotes::support(state).await?),
+        None => None,
+    };
+
+    let text = match (quoting, support) {
+        (Some(quoting), Some(QuoteSupport::Link)) => {
+            quotes::with_link(state, &content.content, quoting).await?
+        }
+        _ => content.content.clone(),
+    };
+
+    let body = PostBody {
+        status: &text,
+        in_reply_to_id: in_reply_to,
+        sensitive: content.cw.is_some(),
+        spoiler_text: content.cw.as_deref(),
+        visibility: Some(visibility.as_str()),
+        quote_id: quoting.filter(|_| support == Some(QuoteSupport::QuoteId)),
+        quoted_status_id: quoting.filter(|_| support == Some(QuoteSupport::QuotedStatusId)),
+        language: content.language.as_deref(),
+        content_type: content.content_type.as_deref(),
+    };
+
+    HttpClient::from_state(state)
+        .idempotency_key(idempotency_key)
+        .post("/api/v1/statuses", &body)
+        .await
+}
+
+/// Post `content` once per idempotency key. A duplicate submission made while the first is
+/// still going waits for it, and one made after gets the same answer without posting again.
+async fn submit(
+    state: &AppState,
+    handle: &tauri::AppHandle,
+    mut content: Content,
+    in_reply_to: Option<String>,
+) -> Result<entities::Status, error::DakkoError> {
+    content.language = content.language.map(|l| l.trim().to_lowercase());
+    if let Some(language) = &content.language {
+        if !language::is_code(language) {
+            return Err(error::DakkoError::Invalid(format!(
+                "{} is not an ISO 639 language code",
+                language
+            )));
+        }
+    }
+
+    let key = content
+        .idempotency_key
+        .clone()
+        .unwrap_or_else(|| auth::random_token(32));
+
+    loop {
+        let (tx, rx) = watch::channel(());
+
+        let waiting = {
+            let mut submissions = state.recent_posts.submissions.lock();
+            submissions.retain(|_, submission| match submission {
+                Submission::InFlight(_) => true,
+                Submission::Posted(at, _) | Submission::Queued(at, _) => {
+                    at.elapsed() < COALESCE_WINDOW
+                }
+            });
+
+            match submissions.get(&key) {
+                Some(Submission::Posted(_, status)) => return Ok(status.clone()),
+                Some(Submission::Queued(_, id)) => {
+                    return Err(error::DakkoError::Queued(id.clone()))
+                }
+                Some(Submission::InFlight(other)) => Some(other.clone()),
+                None => {
+                    submissions.insert(key.clone(), Submission::InFlight(rx));
+                    None
+                }
+            }
+        };
+
+        // Once the first submission is done, look again. If it failed, this one gets its
+        // own attempt.
+        if let Some(mut other) = waiting {
+            let _ = other.changed().await;
+            continue;
+        }
+
+        let _in_flight = InFlight {
+            submissions: &state.recent_posts.submissions,
+            key: key.clone(),
+            _done: tx,
+        };
+
+        let action = outbox::Action::Post {
+            status: with_defaults(state, content).await,
+            in_reply_to,
+            idempotency_key: key.clone(),
+        };
+        let result = outbox::send(state, handle, action).await;
+
+        {
+            let mut submissions = state.recent_posts.submissions.lock();
+            match &result {
+                Ok(status) => {
+                    submissions.insert(key, Submission::Posted(Instant::now(), status.clone()));
+                }
+                Err(error::DakkoError::Queued(id)) => {
+                    submissions.insert(key, Submission::Queued(Instant::now(), id.clone()));
+                }
+                // Left for `_in_flight` to clear, so the next submission tries again
+                Err(_) => {}
+            }
+        }
+
+        return result;
+    }
+}
+
+/// The account's default visibility, made as restrictive as the post being replied to.
+/// Answering a followers only post shouldn't show the answer to everyone.
+async fn default_visibility(state: &AppState, parent: Option<&Visibility>) -> Visibility {
+    let default = settings::current(state).await.default_visibility;
+
+    match parent {
+        Some(parent) => default.most_restrictive(parent.clone()),
+        None => default,
+    }
+}
+
+/// Fill in the visibility and language if the post didn't pick them. Done when the post is
+/// made, so a queued post keeps the defaults it was written under.
+async fn with_defaults(state: &AppState, mut content: Content) -> Content {
+    let settings = settings::current(state).await;
+
+    if content.visibility.is_none() {
+        content.visibility =
+            Some(default_visibility(state, content.in_reply_to_visibility.as_ref()).await);
+    }
+
+    if content.language.is_none() {
+        content.language = language::detect(&content.content)
+            .map(String::from)
+            .or(settings.default_language);
+    }
+
+    content
 }
 
 #[tauri::command]
@@ -17,68 +276,36 @@ pub async fn post_reply(
     post_id: String,
     reply: Content,
     state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
 ) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:statuses")?;
 
-    let client = state.client.read();
-    let client = client.as_ref().unwrap();
-
-    let sensitive = reply.cw.is_some();
-    let options = megalodon::megalodon::PostStatusInputOptions {
-        in_reply_to_id: Some(post_id),
-        sensitive: Some(sensitive),
-        spoiler_text: reply.cw,
-        visibility: Some(reply.visibility),
-        ..Default::default()
-    };
-
-    let res = client.post_status(reply.content, Some(&options)).await?;
-    let output = res.json();
-    match output {
-        megalodon::megalodon::PostStatusOutput::Status(status) => Ok(status),
-        megalodon::megalodon::PostStatusOutput::ScheduledStatus(_) => todo!(),
-    }
+    submit(&state, &handle, reply, Some(post_id)).await
 }
 
 #[tauri::command]
 pub async fn post_status(
     status: Content,
     state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
 ) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:statuses")?;
 
-    let client = state.client.read();
-    let client = client.as_ref().unwrap();
-
-    let sensitive = status.cw.is_some();
-    let options = megalodon::megalodon::PostStatusInputOptions {
-        sensitive: Some(sensitive),
-        spoiler_text: status.cw,
-        visibility: Some(status.visibility),
-        quote_id: status.quoting,
-        ..Default::default()
-    };
-
-    let res = client.post_status(status.content, Some(&options)).await?;
-    let output = res.json();
-    match output {
-        megalodon::megalodon::PostStatusOutput::Status(status) => Ok(status),
-        megalodon::megalodon::PostStatusOutput::ScheduledStatus(_) => todo!(),
-    }
+    submit(&state, &handle, status, None).await
 }
 
 #[tauri::command]
 pub async fn favourite_status(
     id: String,
     state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
 ) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:favourites")?;
 
-    let client = state.client.read();
-    let client = client.as_ref().unwrap();
-
-    let res = client.favourite_status(id).await?;
-    Ok(res.json())
+    outbox::send(&state, &handle, outbox::Action::Favourite { id }).await
 }
 
 #[tauri::command]
@@ -87,11 +314,12 @@ pub async fn get_status(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.get_status(id).await?;
+    let res = request::read(&state.rate_limit, || client.get_status(id.clone())).await?;
     Ok(res.json())
 }
 
@@ -99,55 +327,84 @@ pub async fn get_status(
 pub async fn boost_status(
     id: String,
     state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
 ) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:statuses")?;
 
-    let client = state.client.read();
-    let client = client.as_ref().unwrap();
-
-    let res = client.reblog_status(id).await?;
-    Ok(res.json())
+    outbox::send(&state, &handle, outbox::Action::Boost { id }).await
 }
 
 #[tauri::command]
 pub async fn bookmark_status(
     id: String,
     state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<entities::Status, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:bookmarks")?;
+
+    outbox::send(&state, &handle, outbox::Action::Bookmark { id }).await
+}
+
+#[tauri::command]
+pub async fn unbookmark_status(
+    id: String,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
 ) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:bookmarks")?;
+
+    outbox::send(&state, &handle, outbox::Action::Unbookmark { id }).await
+}
+
+#[tauri::command]
+pub async fn vote_for_poll(
+    poll_id: String,
+    choices: Vec<u32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<entities::Poll, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.bookmark_status(id).await?;
+    let res = request::write(&state.rate_limit, || {
+        client.vote_poll(poll_id.clone(), choices.clone(), None)
+    })
+    .await?;
     Ok(res.json())
 }
 
+/// Stop receiving notifications from a thread
 #[tauri::command]
-pub async fn unbookmark_status(
+pub async fn mute_conversation(
     id: String,
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:mutes")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.unbookmark_status(id).await?;
+    let res = request::write(&state.rate_limit, || client.mute_status(id.clone())).await?;
     Ok(res.json())
 }
 
 #[tauri::command]
-pub async fn vote_for_poll(
-    poll_id: String,
-    choices: Vec<u32>,
+pub async fn unmute_conversation(
+    id: String,
     state: tauri::State<'_, AppState>,
-) -> Result<entities::Poll, error::DakkoError> {
+) -> Result<entities::Status, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:mutes")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.vote_poll(poll_id, choices, None).await?;
+    let res = request::write(&state.rate_limit, || client.unmute_status(id.clone())).await?;
     Ok(res.json())
 }
@@ -1,5 +1,10 @@
 use megalodon::entities;
 
+use crate::auth;
+use crate::filter;
+use crate::page::Page;
+use crate::request;
+use crate::server_filters::{self, FilterContext};
 use crate::state::AppState;
 use crate::error;
 
@@ -9,11 +14,12 @@ pub async fn get_markers(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Marker, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.get_markers(timelines).await?;
+    let res = request::read(&state.rate_limit, || client.get_markers(timelines.clone())).await?;
     Ok(res.json())
 }
 
@@ -24,6 +30,7 @@ pub async fn save_markers(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Marker, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
@@ -37,7 +44,7 @@ pub async fn save_markers(
         }),
     };
 
-    let res = client.save_markers(Some(&options)).await?;
+    let res = request::write(&state.rate_limit, || client.save_markers(Some(&options))).await?;
     Ok(res.json())
 }
 
@@ -47,6 +54,7 @@ pub async fn get_home_catchup(
     state: tauri::State<'_, AppState>,
 ) -> Result<usize, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
@@ -56,7 +64,7 @@ pub async fn get_home_catchup(
         ..Default::default()
     };
 
-    let res = client.get_home_timeline(Some(&options)).await?;
+    let res = request::read(&state.rate_limit, || client.get_home_timeline(Some(&options))).await?;
     Ok(res.json().len())
 }
 
@@ -66,6 +74,7 @@ pub async fn get_public_catchup(
     state: tauri::State<'_, AppState>,
 ) -> Result<usize, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
@@ -75,7 +84,10 @@ pub async fn get_public_catchup(
         ..Default::default()
     };
 
-    let res = client.get_public_timeline(Some(&options)).await?;
+    let res = request::read(&state.rate_limit, || {
+        client.get_public_timeline(Some(&options))
+    })
+    .await?;
     Ok(res.json().len())
 }
 
@@ -84,8 +96,9 @@ pub async fn get_home_timeline(
     start_at: Option<String>,
     limit: u32,
     state: tauri::State<'_, AppState>,
-) -> Result<Vec<entities::Status>, error::DakkoError> {
+) -> Result<Page<server_filters::FilteredStatus>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
@@ -96,35 +109,42 @@ pub async fn get_home_timeline(
         ..Default::default()
     };
 
-    let res = client.get_home_timeline(Some(&options)).await?;
-    Ok(res.json())
+    let res = request::read(&state.rate_limit, || client.get_home_timeline(Some(&options))).await?;
+    Ok(filter::prepare_page(&state, FilterContext::Home, res.json()).await)
 }
 
 #[tauri::command]
 pub async fn get_public_timeline(
     state: tauri::State<'_, AppState>,
+    start_at: Option<String>,
     limit: u32,
-) -> Result<Vec<entities::Status>, error::DakkoError> {
+) -> Result<Page<server_filters::FilteredStatus>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
     let options = megalodon::megalodon::GetPublicTimelineInputOptions {
         limit: Some(limit),
+        max_id: start_at,
         ..Default::default()
     };
 
-    let res = client.get_public_timeline(Some(&options)).await?;
-    Ok(res.json())
+    let res = request::read(&state.rate_limit, || {
+        client.get_public_timeline(Some(&options))
+    })
+    .await?;
+    Ok(filter::prepare_page(&state, FilterContext::Public, res.json()).await)
 }
 
 #[tauri::command]
 pub async fn get_conversation(
     entry_point: String,
     state: tauri::State<'_, AppState>,
-) -> Result<entities::Context, error::DakkoError> {
+) -> Result<server_filters::FilteredContext, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
@@ -134,27 +154,36 @@ pub async fn get_conversation(
         ..Default::default()
     };
 
-    let res = client
-        .get_status_context(entry_point, Some(&options))
-        .await?;
-    Ok(res.json())
+    let res = request::read(&state.rate_limit, || {
+        client.get_status_context(entry_point.clone(), Some(&options))
+    })
+    .await?;
+    let context = res.json();
+
+    Ok(server_filters::FilteredContext {
+        ancestors: filter::prepare(&state, FilterContext::Thread, context.ancestors).await,
+        descendants: filter::prepare(&state, FilterContext::Thread, context.descendants).await,
+    })
 }
 
 #[tauri::command]
 pub async fn get_local_timeline(
+    start_at: Option<String>,
     limit: u32,
     state: tauri::State<'_, AppState>,
-) -> Result<Vec<entities::Status>, error::DakkoError> {
+) -> Result<Page<server_filters::FilteredStatus>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
     let options = megalodon::megalodon::GetLocalTimelineInputOptions {
         limit: Some(limit),
+        max_id: start_at,
         ..Default::default()
     };
 
-    let res = client.get_local_timeline(Some(&options)).await?;
-    Ok(res.json())
+    let res = request::read(&state.rate_limit, || client.get_local_timeline(Some(&options))).await?;
+    Ok(filter::prepare_page(&state, FilterContext::Public, res.json()).await)
 }
@@ -0,0 +1,704 @@
+//! Import and export of social graph data in the CSV formats Mastodon uses for its own
+//! data export, so files move freely between dakko and other clients or servers.
+
+use std::{collections::HashMap, fs, future::Future, time::Duration};
+
+use megalodon::entities;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::auth;
+use crate::error;
+use crate::http::HttpClient;
+use crate::outbox;
+use crate::page::Page;
+use crate::relationship;
+use crate::request;
+use crate::state::AppState;
+use crate::user;
+
+/// Pause between requests, so a large import doesn't exhaust the rate limit straight away
+const THROTTLE: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+    Following,
+    Blocks,
+    Mutes,
+    DomainBlocks,
+    Lists,
+    Bookmarks,
+}
+
+impl TransferKind {
+    /// The scope needed to export (`write == false`) or import this kind
+    fn scope(self, write: bool) -> &'static str {
+        match (self, write) {
+            (TransferKind::Following, false) => "read:follows",
+            (TransferKind::Following, true) => "write:follows",
+            (TransferKind::Blocks | TransferKind::DomainBlocks, false) => "read:blocks",
+            (TransferKind::Blocks | TransferKind::DomainBlocks, true) => "write:blocks",
+            (TransferKind::Mutes, false) => "read:mutes",
+            (TransferKind::Mutes, true) => "write:mutes",
+            (TransferKind::Lists, false) => "read:lists",
+            (TransferKind::Lists, true) => "write:lists",
+            (TransferKind::Bookmarks, false) => "read:bookmarks",
+            (TransferKind::Bookmarks, true) => "write:bookmarks",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferProgress {
+    pub kind: TransferKind,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn write_csv(
+    path: &str,
+    header: Option<&[&str]>,
+    rows: &[Vec<String>],
+) -> Result<(), error::DakkoError> {
+    let mut out = String::new();
+
+    if let Some(header) = header {
+        out.push_str(&header.join(","));
+        out.push('\n');
+    }
+
+    for row in rows {
+        let row: Vec<String> = row.iter().map(|f| escape(f)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+
+    fs::write(path, out)?;
+    Ok(())
+}
+
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => row.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|f| f.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+        .filter(|row| row.iter().any(|f| !f.is_empty()))
+        .collect()
+}
+
+/// Drop the header line Mastodon puts on some of its exports
+fn without_header(mut rows: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    let has_header = rows
+        .first()
+        .and_then(|r| r.first())
+        .map_or(false, |f| f.eq_ignore_ascii_case("account address"));
+    if has_header {
+        rows.remove(0);
+    }
+
+    rows
+}
+
+fn read_rows(path: &str) -> Result<Vec<Vec<String>>, error::DakkoError> {
+    let content = fs::read_to_string(path)?;
+    Ok(without_header(parse_csv(&content)))
+}
+
+fn instance_domain(state: &AppState) -> String {
+    let base_url = state.client_state.read().as_ref().unwrap().base_url.clone();
+    reqwest::Url::parse(&base_url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or(base_url)
+}
+
+/// Local accounts come back without a domain, which would be useless anywhere else
+fn qualified(acct: &str, domain: &str) -> String {
+    if acct.contains('@') {
+        acct.to_string()
+    } else {
+        format!("{}@{}", acct, domain)
+    }
+}
+
+fn parse_bool(value: Option<&String>, default: bool) -> bool {
+    match value.map(|v| v.to_lowercase()) {
+        Some(v) if v == "true" => true,
+        Some(v) if v == "false" => false,
+        _ => default,
+    }
+}
+
+/// The Languages column, a comma separated list. Empty means every language.
+fn parse_languages(value: Option<&String>) -> Option<Vec<String>> {
+    let languages: Vec<String> = value?
+        .split(',')
+        .map(|l| l.trim().to_lowercase())
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    if languages.is_empty() {
+        None
+    } else {
+        Some(languages)
+    }
+}
+
+async fn collect_pages<T, F, Fut>(mut fetch: F) -> Result<Vec<T>, error::DakkoError>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Page<T>, error::DakkoError>>,
+{
+    let mut items = vec![];
+    let mut max_id = None;
+
+    loop {
+        let page = fetch(max_id).await?;
+        let exhausted = page.items.is_empty();
+        items.extend(page.items);
+
+        match page.next {
+            Some(next) if !exhausted => max_id = Some(next),
+            _ => break,
+        }
+
+        tokio::time::sleep(THROTTLE).await;
+    }
+
+    Ok(items)
+}
+
+/// Run an operation, then pause before the next one. Requests wait out rate limits
+/// themselves, this just keeps an import from running into one.
+async fn throttled<T, F, Fut>(op: F) -> Result<T, error::DakkoError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, error::DakkoError>>,
+{
+    let res = op().await;
+    tokio::time::sleep(THROTTLE).await;
+    res
+}
+
+async fn relationships(
+    state: &AppState,
+    ids: Vec<String>,
+) -> Result<Vec<entities::Relationship>, error::DakkoError> {
+    state.relationships.get(state, ids).await
+}
+
+async fn export_rows(
+    kind: TransferKind,
+    state: &AppState,
+) -> Result<(Option<&'static [&'static str]>, Vec<Vec<String>>), error::DakkoError> {
+    let domain = instance_domain(state);
+
+    // The client is locked per request rather than across the export, so the throttle
+    // between pages doesn't keep logins and instance switches waiting
+    match kind {
+        TransferKind::Following => {
+            let me = {
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                request::read(&state.rate_limit, || client.verify_account_credentials())
+                    .await?
+                    .json()
+            };
+            let me = &me.id;
+
+            let accounts = collect_pages(|max_id| async move {
+                let options = megalodon::megalodon::AccountFollowersInputOptions {
+                    limit: Some(80),
+                    max_id,
+                    ..Default::default()
+                };
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                let res = request::read(&state.rate_limit, || {
+                    client.get_account_following(me.clone(), Some(&options))
+                })
+                .await?;
+                Ok(Page::from_headers(res.json, &res.header))
+            })
+            .await?;
+
+            let ids = accounts.iter().map(|a| a.id.clone()).collect();
+            let relationships = relationships(state, ids).await?;
+
+            let rows = accounts
+                .iter()
+                .map(|a| {
+                    let relationship = relationships.iter().find(|r| r.id == a.id);
+                    vec![
+                        qualified(&a.acct, &domain),
+                        relationship.map_or(true, |r| r.showing_reblogs).to_string(),
+                        relationship.map_or(false, |r| r.notifying).to_string(),
+                        String::new(),
+                    ]
+                })
+                .collect();
+
+            Ok((
+                Some(
+                    &[
+                        "Account address",
+                        "Show boosts",
+                        "Notify on new posts",
+                        "Languages",
+                    ][..],
+                ),
+                rows,
+            ))
+        }
+        TransferKind::Blocks => {
+            let accounts = collect_pages(|max_id| async move {
+                let options = megalodon::megalodon::GetBlocksInputOptions {
+                    limit: Some(80),
+                    max_id,
+                    ..Default::default()
+                };
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                let res =
+                    request::read(&state.rate_limit, || client.get_blocks(Some(&options))).await?;
+                Ok(Page::from_headers(res.json, &res.header))
+            })
+            .await?;
+
+            let rows = accounts
+                .iter()
+                .map(|a| vec![qualified(&a.acct, &domain)])
+                .collect();
+
+            Ok((None, rows))
+        }
+        TransferKind::Mutes => {
+            let accounts = collect_pages(|max_id| async move {
+                let options = megalodon::megalodon::GetMutesInputOptions {
+                    limit: Some(80),
+                    max_id,
+                    ..Default::default()
+                };
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                let res =
+                    request::read(&state.rate_limit, || client.get_mutes(Some(&options))).await?;
+                Ok(Page::from_headers(res.json, &res.header))
+            })
+            .await?;
+
+            let ids = accounts.iter().map(|a| a.id.clone()).collect();
+            let relationships = relationships(state, ids).await?;
+
+            let rows = accounts
+                .iter()
+                .map(|a| {
+                    let relationship = relationships.iter().find(|r| r.id == a.id);
+                    vec![
+                        qualified(&a.acct, &domain),
+                        relationship
+                            .map_or(true, |r| r.muting_notifications)
+                            .to_string(),
+                    ]
+                })
+                .collect();
+
+            Ok((Some(&["Account address", "Hide notifications"][..]), rows))
+        }
+        TransferKind::DomainBlocks => {
+            let domains = collect_pages(|max_id| async move {
+                let options = megalodon::megalodon::GetDomainBlocksInputOptions {
+                    limit: Some(200),
+                    max_id,
+                    ..Default::default()
+                };
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                let res = request::read(&state.rate_limit, || {
+                    client.get_domain_blocks(Some(&options))
+                })
+                .await?;
+                Ok(Page::from_headers(res.json, &res.header))
+            })
+            .await?;
+
+            Ok((None, domains.into_iter().map(|d| vec![d]).collect()))
+        }
+        TransferKind::Lists => {
+            let lists = {
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                request::read(&state.rate_limit, || client.get_lists())
+                    .await?
+                    .json()
+            };
+            let mut rows = vec![];
+
+            for list in lists {
+                let list_id = &list.id;
+                let accounts = collect_pages(|max_id| async move {
+                    let options = megalodon::megalodon::GetAccountsInListInputOptions {
+                        limit: Some(80),
+                        max_id,
+                        ..Default::default()
+                    };
+                    let client = state.client.read();
+                    let client = client.as_ref().unwrap();
+                    let res = request::read(&state.rate_limit, || {
+                        client.get_accounts_in_list(list_id.clone(), Some(&options))
+                    })
+                    .await?;
+                    Ok(Page::from_headers(res.json, &res.header))
+                })
+                .await?;
+
+                rows.extend(
+                    accounts
+                        .iter()
+                        .map(|a| vec![list.title.clone(), qualified(&a.acct, &domain)]),
+                );
+            }
+
+            Ok((None, rows))
+        }
+        TransferKind::Bookmarks => {
+            let statuses = collect_pages(|max_id| async move {
+                let options = megalodon::megalodon::GetBookmarksInputOptions {
+                    limit: Some(40),
+                    max_id,
+                    ..Default::default()
+                };
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                let res = request::read(&state.rate_limit, || client.get_bookmarks(Some(&options)))
+                    .await?;
+                Ok(Page::from_headers(res.json, &res.header))
+            })
+            .await?;
+
+            Ok((None, statuses.into_iter().map(|s| vec![s.uri]).collect()))
+        }
+    }
+}
+
+/// Export everything of a kind to `path`, returning how many rows were written
+#[tauri::command]
+pub async fn export_data(
+    kind: TransferKind,
+    path: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, kind.scope(false))?;
+
+    let (header, rows) = export_rows(kind, &state).await?;
+    write_csv(&path, header, &rows)?;
+
+    Ok(rows.len())
+}
+
+#[derive(Deserialize)]
+struct SearchResults {
+    statuses: Vec<entities::Status>,
+}
+
+async fn import_row(
+    kind: TransferKind,
+    row: &[String],
+    lists: &mut HashMap<String, String>,
+    state: &tauri::State<'_, AppState>,
+) -> Result<(), error::DakkoError> {
+    let first = row.first().cloned().unwrap_or_default();
+
+    match kind {
+        TransferKind::Following => {
+            let reblogs = parse_bool(row.get(1), true);
+            let notify = parse_bool(row.get(2), false);
+            let languages = parse_languages(row.get(3));
+
+            let account = throttled(|| user::lookup_account(first.clone(), state.clone())).await?;
+            throttled(|| {
+                relationship::follow_user(
+                    account.id.clone(),
+                    Some(reblogs),
+                    Some(notify),
+                    languages,
+                    state.clone(),
+                )
+            })
+            .await?;
+        }
+        TransferKind::Blocks => {
+            let account = throttled(|| user::lookup_account(first.clone(), state.clone())).await?;
+            throttled(|| relationship::block_user(account.id.clone(), state.clone())).await?;
+        }
+        TransferKind::Mutes => {
+            let notifications = parse_bool(row.get(1), true);
+            let account = throttled(|| user::lookup_account(first.clone(), state.clone())).await?;
+            throttled(|| {
+                relationship::mute_user(
+                    account.id.clone(),
+                    Some(notifications),
+                    None,
+                    state.clone(),
+                )
+            })
+            .await?;
+        }
+        TransferKind::DomainBlocks => {
+            throttled(|| relationship::block_domain(first.clone(), state.clone())).await?;
+        }
+        TransferKind::Lists => {
+            let acct = row.get(1).cloned().unwrap_or_default();
+            let account = throttled(|| user::lookup_account(acct.clone(), state.clone())).await?;
+            let list_id = ensure_list(&first, lists, state).await?;
+
+            throttled(|| async {
+                let client = state.client.read();
+                let client = client.as_ref().unwrap();
+                request::write(&state.rate_limit, || {
+                    client.add_accounts_to_list(list_id.clone(), vec![account.id.clone()])
+                })
+                .await?;
+                Ok(())
+            })
+            .await?;
+        }
+        TransferKind::Bookmarks => {
+            let query = [
+                ("q", first.clone()),
+                ("type", "statuses".to_string()),
+                ("resolve", "true".to_string()),
+            ];
+            let found: SearchResults = throttled(|| async {
+                HttpClient::from_state(state)
+                    .get("/api/v2/search", &query)
+                    .await
+            })
+            .await?;
+
+            let bookmarked = found.statuses.into_iter().next().ok_or_else(|| {
+                error::DakkoError::Invalid(format!("could not find a post at {}", first))
+            })?;
+            // Imports report failures themselves rather than leaving them in the outbox
+            let bookmark = outbox::Action::Bookmark { id: bookmarked.id };
+            throttled(|| outbox::perform(state, &bookmark)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The user's lists by title, fetched once per import
+async fn list_ids(state: &AppState) -> Result<HashMap<String, String>, error::DakkoError> {
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let lists = request::read(&state.rate_limit, || client.get_lists())
+        .await?
+        .json();
+    Ok(lists.into_iter().map(|l| (l.title, l.id)).collect())
+}
+
+async fn ensure_list(
+    title: &str,
+    lists: &mut HashMap<String, String>,
+    state: &AppState,
+) -> Result<String, error::DakkoError> {
+    if let Some(id) = lists.get(title) {
+        return Ok(id.clone());
+    }
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let list = request::write(&state.rate_limit, || client.create_list(title.to_string()))
+        .await?
+        .json();
+    lists.insert(title.to_string(), list.id.clone());
+    Ok(list.id)
+}
+
+/// Import a Mastodon CSV export, emitting `import-progress` as each row is applied.
+/// Rows that fail are reported back rather than aborting the whole import.
+#[tauri::command]
+pub async fn import_data(
+    kind: TransferKind,
+    path: String,
+    state: tauri::State<'_, AppState>,
+    handle: tauri::AppHandle,
+) -> Result<ImportReport, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, kind.scope(true))?;
+
+    let rows = read_rows(&path)?;
+    let mut report = ImportReport {
+        total: rows.len(),
+        ..Default::default()
+    };
+
+    let mut lists = match kind {
+        TransferKind::Lists => list_ids(&state).await?,
+        _ => HashMap::new(),
+    };
+
+    for (done, row) in rows.iter().enumerate() {
+        match import_row(kind, row, &mut lists, &state).await {
+            Ok(()) => report.succeeded += 1,
+            Err(err) => report.failed.push((row.join(","), err.to_string())),
+        }
+
+        handle
+            .emit_all(
+                "import-progress",
+                TransferProgress {
+                    kind,
+                    done: done + 1,
+                    total: report.total,
+                },
+            )
+            .unwrap();
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|f| f.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parses_plain_rows() {
+        assert_eq!(
+            parse_csv("a@one.social,true\r\nb@two.social,false\n"),
+            rows(&[&["a@one.social", "true"], &["b@two.social", "false"]])
+        );
+
+        // No trailing newline, blank lines and padding around fields
+        assert_eq!(
+            parse_csv("\n a@one.social , true \n\nb@two.social"),
+            rows(&[&["a@one.social", "true"], &["b@two.social"]])
+        );
+    }
+
+    #[test]
+    fn parses_quoted_fields() {
+        assert_eq!(
+            parse_csv("\"Friends, close\",a@one.social\n"),
+            rows(&[&["Friends, close", "a@one.social"]])
+        );
+        assert_eq!(
+            parse_csv("\"say \"\"hi\"\"\",x\n"),
+            rows(&[&["say \"hi\"", "x"]])
+        );
+        assert_eq!(
+            parse_csv("\"two\nlines\",x\n"),
+            rows(&[&["two\nlines", "x"]])
+        );
+        assert_eq!(parse_csv("a,\"\",c"), rows(&[&["a", "", "c"]]));
+    }
+
+    #[test]
+    fn escapes_round_trip() {
+        let fields = [
+            "plain",
+            "with, comma",
+            "with \"quotes\"",
+            "with\nnewline",
+            "",
+        ];
+        for field in fields {
+            let line = format!("{},end\n", escape(field));
+            assert_eq!(parse_csv(&line), rows(&[&[field, "end"]]), "{:?}", field);
+        }
+
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a,b"), "\"a,b\"");
+        assert_eq!(escape("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn drops_only_a_mastodon_header() {
+        let exported = parse_csv(
+            "Account address,Show boosts,Notify on new posts,Languages\na@one.social,true,false,\n",
+        );
+        assert_eq!(
+            without_header(exported),
+            rows(&[&["a@one.social", "true", "false", ""]])
+        );
+
+        let headerless = rows(&[&["a@one.social", "true"]]);
+        assert_eq!(without_header(headerless.clone()), headerless);
+        assert_eq!(
+            without_header(rows(&[&["ACCOUNT ADDRESS"]])),
+            Vec::<Vec<String>>::new()
+        );
+    }
+
+    #[test]
+    fn parses_follow_columns() {
+        assert!(parse_bool(Some(&"TRUE".to_string()), false));
+        assert!(!parse_bool(Some(&"false".to_string()), true));
+        assert!(parse_bool(Some(&"".to_string()), true));
+        assert!(!parse_bool(None, false));
+
+        assert_eq!(
+            parse_languages(Some(&"en, DE".to_string())),
+            Some(vec!["en".to_string(), "de".to_string()])
+        );
+        assert_eq!(parse_languages(Some(&" , ".to_string())), None);
+        assert_eq!(parse_languages(None), None);
+    }
+}
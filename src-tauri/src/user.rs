@@ -1,16 +1,28 @@
 use megalodon::entities;
+use reqwest::multipart::{Form, Part};
+use reqwest::Method;
+use serde::Deserialize;
 
+use crate::auth;
+use crate::capabilities;
 use crate::error;
+use crate::filter;
+use crate::http::HttpClient;
+use crate::language;
+use crate::page::Page;
+use crate::request;
+use crate::server_filters::{self, FilterContext};
 use crate::state::AppState;
 
 #[tauri::command]
 pub async fn get_bookmarks(state: tauri::State<'_, AppState>) -> Result<Vec<entities::Status>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:bookmarks")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.get_bookmarks(None).await?;
+    let res = request::read(&state.rate_limit, || client.get_bookmarks(None)).await?;
     Ok(res.json())
 }
 
@@ -21,29 +33,171 @@ pub async fn get_emojis(state: tauri::State<'_, AppState>) -> Result<Vec<entitie
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.get_instance_custom_emojis().await?;
+    let res = request::read(&state.rate_limit, || client.get_instance_custom_emojis()).await?;
     Ok(res.json())
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StatusesQuery {
+    pub limit: Option<u32>,
+    pub max_id: Option<String>,
+    pub since_id: Option<String>,
+    pub pinned: Option<bool>,
+    pub exclude_replies: Option<bool>,
+    pub exclude_reblogs: Option<bool>,
+    pub only_media: Option<bool>,
+}
+
 #[tauri::command]
 pub async fn get_statuses(
     id: String,
+    query: Option<StatusesQuery>,
     state: tauri::State<'_, AppState>,
-) -> Result<Vec<entities::Status>, error::DakkoError> {
+) -> Result<Vec<server_filters::FilteredStatus>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:statuses")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
+    let query = query.unwrap_or_default();
     let options = megalodon::megalodon::GetAccountStatusesInputOptions {
-        limit: Some(25),
+        limit: Some(query.limit.unwrap_or(25)),
+        max_id: query.max_id,
+        since_id: query.since_id,
+        pinned: query.pinned,
+        exclude_replies: query.exclude_replies,
+        exclude_reblogs: query.exclude_reblogs,
+        only_media: query.only_media,
         ..Default::default()
     };
 
-    let res = client.get_account_statuses(id, Some(&options)).await?;
+    let res = request::read(&state.rate_limit, || {
+        client.get_account_statuses(id.clone(), Some(&options))
+    })
+    .await?;
+    Ok(filter::prepare(&state, FilterContext::Account, res.json()).await)
+}
+
+#[tauri::command]
+pub async fn get_account(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<entities::Account, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:accounts")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let res = request::read(&state.rate_limit, || client.get_account(id.clone())).await?;
     Ok(res.json())
 }
 
+#[tauri::command]
+pub async fn lookup_account(
+    acct: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<entities::Account, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:accounts")?;
+
+    // Accept both `@user@domain` and `user@domain`
+    let acct = acct.trim().trim_start_matches('@').to_string();
+    if acct.is_empty() {
+        return Err(error::DakkoError::Invalid("handle cannot be empty".to_string()));
+    }
+
+    let looked_up = HttpClient::from_state(&state)
+        .get::<entities::Account>("/api/v1/accounts/lookup", &[("acct", acct.clone())])
+        .await;
+
+    match looked_up {
+        Ok(account) => Ok(account),
+        Err(error::DakkoError::Status { status: 404, .. }) => {
+            // Not every server has the lookup endpoint, or knows about remote accounts
+            // until asked to resolve them, so fall back to a resolving search.
+            let client = state.client.read();
+            let client = client.as_ref().unwrap();
+
+            let options = megalodon::megalodon::SearchAccountInputOptions {
+                resolve: Some(true),
+                limit: Some(5),
+                ..Default::default()
+            };
+
+            // Local accounts come back without a domain
+            let username = acct.split('@').next().unwrap_or_default().to_string();
+            let res = request::read(&state.rate_limit, || {
+                client.search_account(acct.clone(), Some(&options))
+            })
+            .await?;
+            res.json()
+                .into_iter()
+                .find(|a| {
+                    a.acct.eq_ignore_ascii_case(&acct)
+                        || (!a.acct.contains('@') && a.acct.eq_ignore_ascii_case(&username))
+                })
+                .ok_or_else(|| {
+                    error::DakkoError::Invalid(format!("no account found for @{}", acct))
+                })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[tauri::command]
+pub async fn get_followers(
+    id: String,
+    limit: Option<u32>,
+    max_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Page<entities::Account>, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:accounts")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let options = megalodon::megalodon::AccountFollowersInputOptions {
+        limit: Some(limit.unwrap_or(40)),
+        max_id,
+        ..Default::default()
+    };
+
+    let res = request::read(&state.rate_limit, || {
+        client.get_account_followers(id.clone(), Some(&options))
+    })
+    .await?;
+    Ok(Page::from_headers(res.json, &res.header))
+}
+
+#[tauri::command]
+pub async fn get_following(
+    id: String,
+    limit: Option<u32>,
+    max_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Page<entities::Account>, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:accounts")?;
+
+    let client = state.client.read();
+    let client = client.as_ref().unwrap();
+
+    let options = megalodon::megalodon::AccountFollowersInputOptions {
+        limit: Some(limit.unwrap_or(40)),
+        max_id,
+        ..Default::default()
+    };
+
+    let res = request::read(&state.rate_limit, || {
+        client.get_account_following(id.clone(), Some(&options))
+    })
+    .await?;
+    Ok(Page::from_headers(res.json, &res.header))
+}
+
 #[tauri::command]
 pub async fn get_instance(
     state: tauri::State<'_, AppState>,
@@ -51,7 +205,7 @@ pub async fn get_instance(
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.get_instance().await?;
+    let res = request::read(&state.rate_limit, || client.get_instance()).await?;
     Ok(res.json())
 }
 
@@ -60,20 +214,234 @@ pub async fn get_user(
     state: tauri::State<'_, AppState>,
 ) -> Result<entities::Account, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:accounts")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
 
-    let res = client.verify_account_credentials().await?;
+    let res = request::read(&state.rate_limit, || client.verify_account_credentials()).await?;
     Ok(res.json())
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileField {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CredentialsUpdate {
+    pub display_name: Option<String>,
+    pub note: Option<String>,
+    /// Path to an image on disk
+    pub avatar: Option<String>,
+    /// Path to an image on disk
+    pub header: Option<String>,
+    /// Replaces every profile field when set
+    pub fields: Option<Vec<ProfileField>>,
+    pub locked: Option<bool>,
+    pub bot: Option<bool>,
+    pub discoverable: Option<bool>,
+    pub privacy: Option<entities::status::StatusVisibility>,
+    pub sensitive: Option<bool>,
+    pub language: Option<String>,
+}
+
+struct ProfileLimits {
+    max_fields: usize,
+    max_name_length: usize,
+    max_value_length: usize,
+}
+
+impl Default for ProfileLimits {
+    // Mastodon's limits, which it does not advertise
+    fn default() -> Self {
+        Self {
+            max_fields: 4,
+            max_name_length: 255,
+            max_value_length: 255,
+        }
+    }
+}
+
+async fn profile_limits(state: &AppState) -> ProfileLimits {
+    let defaults = ProfileLimits::default();
+    let instance = capabilities::instance(state).await;
+
+    // Pleroma and friends advertise their limits under pleroma.metadata
+    let limits = match instance {
+        Ok(instance) => instance["pleroma"]["metadata"]["fields_limits"].clone(),
+        Err(_) => return defaults,
+    };
+
+    let limit = |key: &str, default: usize| {
+        limits[key].as_u64().map(|v| v as usize).unwrap_or(default)
+    };
+
+    ProfileLimits {
+        max_fields: limit("max_fields", defaults.max_fields),
+        max_name_length: limit("name_length", defaults.max_name_length),
+        max_value_length: limit("value_length", defaults.max_value_length),
+    }
+}
+
+impl CredentialsUpdate {
+    fn validate(&self, limits: &ProfileLimits) -> Result<(), error::DakkoError> {
+        if let Some(fields) = &self.fields {
+            if fields.len() > limits.max_fields {
+                return Err(error::DakkoError::Invalid(format!(
+                    "this instance allows at most {} profile fields",
+                    limits.max_fields
+                )));
+            }
+
+            for field in fields {
+                if field.name.chars().count() > limits.max_name_length {
+                    return Err(error::DakkoError::Invalid(format!(
+                        "profile field name '{}' is longer than {} characters",
+                        field.name, limits.max_name_length
+                    )));
+                }
+
+                if field.value.chars().count() > limits.max_value_length {
+                    return Err(error::DakkoError::Invalid(format!(
+                        "profile field '{}' is longer than {} characters",
+                        field.name, limits.max_value_length
+                    )));
+                }
+            }
+        }
+
+        for path in [&self.avatar, &self.header].into_iter().flatten() {
+            if !std::path::Path::new(path).is_file() {
+                return Err(error::DakkoError::Invalid(format!("'{}' is not a file", path)));
+            }
+        }
+
+        if let Some(language) = &self.language {
+            if !language::is_code(language) {
+                return Err(error::DakkoError::Invalid(format!(
+                    "'{}' is not an ISO 639 language code",
+                    language
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The update as the multipart form `update_credentials` takes, which is the only way
+    /// to send it images
+    fn form(&self, avatar: Option<&Upload>, header: Option<&Upload>) -> Form {
+        let mut values = vec![];
+        let mut text = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                values.push((name.to_string(), value));
+            }
+        };
+
+        text("display_name", self.display_name.clone());
+        text("note", self.note.clone());
+        text("locked", self.locked.map(|v| v.to_string()));
+        text("bot", self.bot.map(|v| v.to_string()));
+        text("discoverable", self.discoverable.map(|v| v.to_string()));
+        let privacy = self.privacy.as_ref().map(|p| p.to_string());
+        text("source[privacy]", privacy);
+        text("source[sensitive]", self.sensitive.map(|v| v.to_string()));
+        text("source[language]", self.language.clone());
+
+        for (i, field) in self.fields.iter().flatten().enumerate() {
+            let key = |attribute: &str| format!("fields_attributes[{}][{}]", i, attribute);
+            values.push((key("name"), field.name.clone()));
+            values.push((key("value"), field.value.clone()));
+        }
+
+        let mut form = values
+            .into_iter()
+            .fold(Form::new(), |form, (name, value)| form.text(name, value));
+
+        if let Some(avatar) = avatar {
+            form = form.part("avatar", avatar.part());
+        }
+
+        if let Some(header) = header {
+            form = form.part("header", header.part());
+        }
+
+        form
+    }
+}
+
+/// An image for the profile, read up front so every attempt uploads the same bytes
+struct Upload {
+    file_name: String,
+    mime: &'static str,
+    bytes: Vec<u8>,
+}
+
+impl Upload {
+    fn read(path: &str) -> Result<Self, error::DakkoError> {
+        let path = std::path::Path::new(path);
+        let bytes = std::fs::read(path)?;
+
+        let file_name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => "image".to_string(),
+        };
+
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        let mime = match extension.as_deref() {
+            Some("png") => "image/png",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            Some("avif") => "image/avif",
+            _ => "application/octet-stream",
+        };
+
+        Ok(Self {
+            file_name,
+            mime,
+            bytes,
+        })
+    }
+
+    fn part(&self) -> Part {
+        Part::bytes(self.bytes.clone())
+            .file_name(self.file_name.clone())
+            .mime_str(self.mime)
+            .expect("upload MIME types are valid")
+    }
+}
+
+#[tauri::command]
+pub async fn update_credentials(
+    update: CredentialsUpdate,
+    state: tauri::State<'_, AppState>,
+) -> Result<entities::Account, error::DakkoError> {
+    assert!(state.has_logged_in());
+    auth::require_scope(&state, "write:accounts")?;
+
+    let limits = profile_limits(&state).await;
+    update.validate(&limits)?;
+
+    let avatar = update.avatar.as_deref().map(Upload::read).transpose()?;
+    let header = update.header.as_deref().map(Upload::read).transpose()?;
+
+    HttpClient::from_state(&state)
+        .send_form(Method::PATCH, "/api/v1/accounts/update_credentials", || {
+            update.form(avatar.as_ref(), header.as_ref())
+        })
+        .await
+}
+
 #[tauri::command]
 pub async fn get_notifications(
     since: Option<String>,
     state: tauri::State<'_, AppState>,
 ) -> Result<Vec<entities::Notification>, error::DakkoError> {
     assert!(state.has_logged_in());
+    auth::require_scope(&state, "read:notifications")?;
 
     let client = state.client.read();
     let client = client.as_ref().unwrap();
@@ -84,6 +452,99 @@ pub async fn get_notifications(
         ..Default::default()
     };
 
-    let res = client.get_notifications(Some(&options)).await?;
+    let res = request::read(&state.rate_limit, || client.get_notifications(Some(&options))).await?;
     Ok(res.json())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Send `form` to a local listener and return the raw request it received
+    async fn sent(form: Form) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 4096];
+            // The form has a known length, so it ends with the closing boundary
+            while !request.ends_with(b"--\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            socket
+                .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        reqwest::Client::new()
+            .patch(url)
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        server.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn uploads_images_as_files() {
+        let path = std::env::temp_dir().join("dakko-update-credentials-avatar.png");
+        std::fs::write(&path, b"not really a png").unwrap();
+        let avatar = Upload::read(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let update = CredentialsUpdate {
+            display_name: Some("Dakko".to_string()),
+            ..Default::default()
+        };
+        let request = sent(update.form(Some(&avatar), None)).await;
+
+        assert!(request.contains(
+            "name=\"avatar\"; filename=\"dakko-update-credentials-avatar.png\"\r\n\
+             Content-Type: image/png\r\n\r\nnot really a png"
+        ));
+        assert!(request.contains("name=\"display_name\"\r\n\r\nDakko"));
+        assert!(!request.contains("name=\"header\""));
+    }
+
+    #[tokio::test]
+    async fn sends_fields_and_source_as_attributes() {
+        let update = CredentialsUpdate {
+            fields: Some(vec![ProfileField {
+                name: "pronouns".to_string(),
+                value: "they/them".to_string(),
+            }]),
+            language: Some("fil".to_string()),
+            ..Default::default()
+        };
+        let request = sent(update.form(None, None)).await;
+
+        assert!(request.contains("name=\"fields_attributes[0][name]\"\r\n\r\npronouns"));
+        assert!(request.contains("name=\"fields_attributes[0][value]\"\r\n\r\nthey/them"));
+        assert!(request.contains("name=\"source[language]\"\r\n\r\nfil"));
+    }
+
+    #[test]
+    fn accepts_three_letter_languages() {
+        let limits = ProfileLimits::default();
+        let update = |language: &str| CredentialsUpdate {
+            language: Some(language.to_string()),
+            ..Default::default()
+        };
+
+        assert!(update("en").validate(&limits).is_ok());
+        assert!(update("fil").validate(&limits).is_ok());
+        assert!(update("english").validate(&limits).is_err());
+        assert!(update("EN").validate(&limits).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+use crate::error;
+
+/// Who can see a post. The Mastodon four work everywhere. Others, like Akkoma's `local` or
+/// Fedibird's `mutual`, only where the instance's capabilities list them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Visibility(String);
+
+pub const STANDARD: [&str; 4] = ["public", "unlisted", "private", "direct"];
+
+impl Visibility {
+    pub fn new(name: &str) -> Result<Self, error::DakkoError> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c == '_' || c == '-');
+        if !valid {
+            return Err(error::DakkoError::Invalid(format!(
+                "{} is not a visibility",
+                name
+            )));
+        }
+
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn public() -> Self {
+        Self("public".to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// How few people can see a post, higher is fewer
+    fn rank(&self) -> u8 {
+        match self.0.as_str() {
+            "public" => 0,
+            "unlisted" => 1,
+            "local" | "login" => 2,
+            "private" => 3,
+            "mutual" | "limited" => 4,
+            "direct" => 5,
+            "personal" => 6,
+            // Something we don't know, assume it is meant to be kept close
+            _ => 3,
+        }
+    }
+
+    /// Whichever of the two shows the post to fewer people
+    pub fn most_restrictive(self, other: Self) -> Self {
+        if other.rank() > self.rank() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Visibility {
+    // Through `new`, so what the frontend or a config file hands us is checked the same way
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::new(&name).map_err(D::Error::custom)
+    }
+}
@@ -57,7 +57,11 @@ export interface Status {
 	pinned: boolean | undefined;
 	emoji_reactions: unknown[] | undefined;
 	quote: boolean;
+	// What it quotes, filled in on statuses from timelines
+	quoted: Quote | undefined;
 	bookmarked: boolean | undefined;
+	filtered: FilterResult[] | undefined;
+	rule: RuleMatch | undefined;
 }
 
 export enum StatusVisibility {
@@ -67,6 +71,9 @@ export enum StatusVisibility {
 	DIRECT = 'direct'
 }
 
+// One of StatusVisibility, or an instance specific one like `local` or `mutual`
+export type Visibility = string;
+
 export enum AttachmentType {
 	IMAGE = 'image',
 	GIFV = 'gifv',
@@ -87,6 +94,51 @@ export interface Attachment {
 	blurhash: string | undefined;
 }
 
+export type ServerFilterContext = 'home' | 'notifications' | 'public' | 'thread' | 'account';
+export type ServerFilterAction = 'warn' | 'hide' | 'blur';
+
+export interface ServerFilterKeyword {
+	id: string;
+	keyword: string;
+	whole_word: boolean;
+}
+
+export interface ServerFilter {
+	id: string;
+	title: string;
+	context: ServerFilterContext[];
+	expires_at: string | undefined;
+	filter_action: ServerFilterAction;
+	keywords: ServerFilterKeyword[];
+}
+
+export interface ServerFilterInput {
+	title: string;
+	context: ServerFilterContext[];
+	filter_action: ServerFilterAction;
+	expires_in: number | undefined;
+	keywords: { id?: string; keyword: string; whole_word: boolean; destroy?: boolean }[];
+}
+
+export interface FilterResult {
+	filter: ServerFilter;
+	keyword_matches: string[];
+}
+
+export type FilterOutcome = 'show' | 'warn' | 'hide';
+
+export interface FilterRule {
+	name: string;
+	enabled: boolean;
+	rule: string;
+	outcome: FilterOutcome;
+}
+
+export interface RuleMatch {
+	name: string;
+	outcome: FilterOutcome;
+}
+
 export interface StatusContext {
 	ancestors: Status[];
 	descendants: Status[];
@@ -99,3 +151,11 @@ export interface CustomEmoji {
 	visible_in_picker: boolean;
 	category: string | undefined;
 }
+
+export interface Quote {
+	url: string;
+	// Missing if our instance could not fetch the quoted post
+	status: Status | undefined;
+}
+
+export type QuoteSupport = 'quote_id' | 'quoted_status_id' | 'link';
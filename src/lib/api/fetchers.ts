@@ -14,8 +14,40 @@ function makeStatusAction<R>(key: string): (targetStatusId: string) => Promise<R
   return (targetStatusId: string) => fetch(key, { id: targetStatusId })
 }
 
+// Commands fail with `{ kind, message }`, so a queued write can be told apart from a failed one.
+// Queued writes also carry the outbox `id` their `outbox-sent` event will have.
+export class CommandError extends Error {
+  constructor(public kind: string, message: string, public id?: string) {
+    super(message)
+  }
+
+  toString(): string {
+    return this.message
+  }
+}
+
+export const isQueued = (err: unknown): boolean =>
+  err instanceof CommandError && err.kind === 'queued'
+
+// A write that went into the outbox will be sent by itself, so callers can carry on as if it had
+export async function sentOrQueued<T>(write: Promise<T>): Promise<T | undefined> {
+  try {
+    return await write
+  } catch (err) {
+    if (isQueued(err)) {
+      return undefined
+    }
+    throw err
+  }
+}
+
 async function fetch<R>(...params: Parameters<typeof invoke>): Promise<R> {
-  return invoke(...params) as Promise<R>;
+  try {
+    return await (invoke(...params) as Promise<R>);
+  } catch (err) {
+    const { kind, message, id } = (err ?? {}) as { kind?: string, message?: string, id?: string }
+    throw kind && message !== undefined ? new CommandError(kind, message, id) : err
+  }
 }
 
 export const fetchLoginState = makeSimpleFetcher<api.LoginStatus>('login_state');
@@ -25,9 +57,28 @@ export const fetchSelf = makeSimpleFetcher<api.Account>('get_user');
 export async function setInstance(instanceURL: string, instanceType: InstanceType): Promise<void> {
   return fetch('set_instance', { url: instanceURL, instanceType });
 }
-export const fetchLoginURL = makeSimpleFetcher<string>('login');
+export type LoginMode = 'loopback' | 'out_of_band' | 'deep_link';
+
+export async function fetchLoginURL(mode?: LoginMode, scopes?: string[]): Promise<string> {
+  return fetch('login', { mode, scopes });
+}
+export const takeConfigErrors = makeSimpleFetcher<string[]>('take_config_errors');
+export const openLaunchLink = makeSimpleFetcher<void>('open_launch_link');
+export const fetchRateLimit = makeSimpleFetcher<api.RateBudget>('get_rate_limit');
+export const fetchOutbox = makeSimpleFetcher<api.OutboxEntry[]>('get_outbox');
+export async function retryOutbox(id?: string): Promise<void> {
+  return fetch('retry_outbox', { id });
+}
+export async function discardOutboxEntry(id: string): Promise<void> {
+  return fetch('discard_outbox_entry', { id });
+}
+export async function submitAuthCode(code: string): Promise<void> {
+  return fetch('submit_auth_code', { code });
+}
 export const fetchBookmarks = makeSimpleFetcher<api.Status[]>('get_bookmarks');
-export const fetchFollowRequests = makeSimpleFetcher<api.FollowRequest[]>('get_follow_requests');
+export async function fetchFollowRequests(maxId?: string): Promise<api.Page<api.FollowRequest>> {
+  return fetch('get_follow_requests', { maxId });
+}
 export const fetchCustomEmojis = makeSimpleFetcher<api.CustomEmoji[]>('get_emojis');
 
 export async function fetchNotifications(sinceId?: string): Promise<api.Notification[]> {
@@ -45,7 +96,9 @@ export const fetchStatuses = makeAccountAction<api.Status[]>('get_statuses')
 
 export const followUser = makeAccountAction<api.Relationship>('follow_user')
 export const unfollowUser = makeAccountAction<api.Relationship>('unfollow_user')
-export const muteUser = makeAccountAction<api.Relationship>('mute_user')
+export async function muteUser(targetAccountId: string, notifications?: boolean, duration?: number): Promise<api.Relationship> {
+  return fetch('mute_user', { id: targetAccountId, notifications, duration })
+}
 export const unmuteUser = makeAccountAction<api.Relationship>('unumte_user')
 export const blockUser = makeAccountAction<api.Relationship>('block_user')
 export const unblockUser = makeAccountAction<api.Relationship>('unumte_user')
@@ -60,8 +113,18 @@ export const unfavouriteStatus = makeStatusAction<void>('unfavourite_status')
 export interface StatusContent {
   content: string
   cw: string | undefined
-  visibility: api.StatusVisibility
+  // Left out, the account's default visibility is used, or the parent's for a reply if
+  // that is more restrictive
+  visibility?: api.Visibility
+  // What the post being replied to was made as, so a reply doesn't reach further
+  in_reply_to_visibility?: api.Visibility
   quoting?: string
+  // Left out, the backend detects it or uses the default language
+  language?: string
+  // One of the instance's post formats, plain text if left out
+  content_type?: string
+  // The same for every submission of one post, so the backend only posts it once
+  idempotency_key?: string
 }
 export async function replyToStatus(statusId: string, reply: StatusContent): Promise<void> {
   return fetch('post_reply', {
@@ -76,6 +139,28 @@ export async function postStatus(status: StatusContent): Promise<api.Status> {
   })
 }
 
+export const fetchDrafts = makeSimpleFetcher<api.Draft[]>('get_drafts')
+export async function createDraft(draft: api.DraftContent): Promise<api.Draft> {
+  return fetch('create_draft', { draft })
+}
+export async function updateDraft(id: string, draft: api.DraftContent): Promise<api.Draft> {
+  return fetch('update_draft', { id, draft })
+}
+export async function deleteDraft(id: string): Promise<void> {
+  return fetch('delete_draft', { id })
+}
+
+export const fetchQuoteSupport = makeSimpleFetcher<api.QuoteSupport>('get_quote_support')
+
+export async function detectLanguage(text: string): Promise<string | undefined> {
+  return fetch('detect_language', { text })
+}
+
+export const fetchCapabilities = makeSimpleFetcher<api.Capabilities>('get_capabilities')
+export async function previewMarkdown(content: string): Promise<string> {
+  return fetch('preview_markdown', { content })
+}
+
 export async function fetchMarker(): Promise<api.Marker> {
   return fetch('get_markers', {
     timelines: ['home', 'notifications']
@@ -97,4 +182,120 @@ export async function voteForPoll(poll: api.Poll, choices: number[]): Promise<ap
 }
 
 export const acceptFollowRequest = makeAccountAction<api.Relationship>('accept_follow_request')
-export const denyFollowRequest = makeAccountAction<api.Relationship>('deny_follow_request')
\ No newline at end of file
+export const denyFollowRequest = makeAccountAction<api.Relationship>('deny_follow_request')
+export const fetchSettings = makeSimpleFetcher<api.SettingsView>('get_settings')
+export async function updateSettings(global?: api.Settings, overrides?: api.SettingsOverride): Promise<api.SettingsView> {
+  return fetch('update_settings', { global, overrides })
+}
+export const fetchNotifierSettings = makeSimpleFetcher<api.NotifierSettings>('get_notifier_settings')
+export async function updateNotifierSettings(settings: api.NotifierSettings): Promise<api.NotifierSettings> {
+  return fetch('update_notifier_settings', { settings })
+}
+
+export const fetchServerFilters = makeSimpleFetcher<api.ServerFilter[]>('get_server_filters')
+export async function createServerFilter(filter: api.ServerFilterInput): Promise<api.ServerFilter> {
+  return fetch('create_server_filter', { filter })
+}
+export async function updateServerFilter(id: string, filter: api.ServerFilterInput): Promise<api.ServerFilter> {
+  return fetch('update_server_filter', { id, filter })
+}
+export async function deleteServerFilter(id: string): Promise<void> {
+  return fetch('delete_server_filter', { id })
+}
+
+export const fetchFilterRules = makeSimpleFetcher<api.FilterRule[]>('get_filter_rules')
+export async function saveFilterRules(rules: api.FilterRule[]): Promise<api.FilterRule[]> {
+  return fetch('set_filter_rules', { rules })
+}
+
+export interface StatusesQuery {
+  limit?: number
+  max_id?: string
+  since_id?: string
+  pinned?: boolean
+  exclude_replies?: boolean
+  exclude_reblogs?: boolean
+  only_media?: boolean
+}
+
+export async function fetchAccountStatuses(accountId: string, query: StatusesQuery): Promise<api.Status[]> {
+  return fetch('get_statuses', { id: accountId, query })
+}
+export const fetchAccount = makeAccountAction<api.Account>('get_account')
+export async function lookupAccount(acct: string): Promise<api.Account> {
+  return fetch('lookup_account', { acct })
+}
+export async function fetchFollowers(accountId: string, maxId?: string): Promise<api.Page<api.Account>> {
+  return fetch('get_followers', { id: accountId, maxId })
+}
+export async function fetchFollowing(accountId: string, maxId?: string): Promise<api.Page<api.Account>> {
+  return fetch('get_following', { id: accountId, maxId })
+}
+
+export interface CredentialsUpdate {
+  display_name?: string
+  note?: string
+  avatar?: string
+  header?: string
+  fields?: { name: string, value: string }[]
+  locked?: boolean
+  bot?: boolean
+  discoverable?: boolean
+  privacy?: api.StatusVisibility
+  sensitive?: boolean
+  language?: string
+}
+
+export async function updateCredentials(update: CredentialsUpdate): Promise<api.Account> {
+  return fetch('update_credentials', { update })
+}
+
+export async function acceptFollowRequests(ids: string[]): Promise<api.BulkResult> {
+  return fetch('accept_follow_requests', { ids })
+}
+export async function denyFollowRequests(ids: string[]): Promise<api.BulkResult> {
+  return fetch('deny_follow_requests', { ids })
+}
+
+export async function fetchMutes(maxId?: string): Promise<api.Page<api.Account>> {
+  return fetch('get_mutes', { maxId })
+}
+export async function fetchBlocks(maxId?: string): Promise<api.Page<api.Account>> {
+  return fetch('get_blocks', { maxId })
+}
+export const muteConversation = makeStatusAction<api.Status>('mute_conversation')
+export const unmuteConversation = makeStatusAction<api.Status>('unmute_conversation')
+
+export async function fetchDomainBlocks(maxId?: string): Promise<api.Page<string>> {
+  return fetch('get_domain_blocks', { maxId })
+}
+export async function blockDomain(domain: string): Promise<void> {
+  return fetch('block_domain', { domain })
+}
+export async function unblockDomain(domain: string): Promise<void> {
+  return fetch('unblock_domain', { domain })
+}
+
+export type TransferKind = 'following' | 'blocks' | 'mutes' | 'domain_blocks' | 'lists' | 'bookmarks'
+
+export interface ImportReport {
+  total: number
+  succeeded: number
+  failed: [string, string][]
+}
+
+export async function exportData(kind: TransferKind, path: string): Promise<number> {
+  return fetch('export_data', { kind, path })
+}
+export async function importData(kind: TransferKind, path: string): Promise<ImportReport> {
+  return fetch('import_data', { kind, path })
+}
+
+export async function setAccountNote(targetAccountId: string, note?: string): Promise<api.Relationship> {
+  return fetch('set_account_note', { id: targetAccountId, note })
+}
+export const endorseAccount = makeAccountAction<api.Relationship>('endorse_account')
+export const unendorseAccount = makeAccountAction<api.Relationship>('unendorse_account')
+export async function fetchEndorsements(maxId?: string): Promise<api.Page<api.Account>> {
+  return fetch('get_endorsements', { maxId })
+}
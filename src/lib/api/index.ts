@@ -1,3 +1,4 @@
+import type { Emoji, Status } from './content';
 import type { Account } from './account';
 
 export * from './account';
@@ -6,4 +7,32 @@ export * from './user';
 export * from './instance';
 export * from './fetchers'
 
-export type FollowRequest = Account;
+export interface FollowRequest {
+	id: string;
+	username: string;
+	acct: string;
+	display_name: string;
+	note: string;
+	url: string;
+	avatar: string;
+	header: string;
+	locked: boolean;
+	bot: boolean;
+	emojis: Emoji[];
+}
+
+export interface BulkResult {
+	succeeded: string[];
+	failed: [string, string][];
+}
+
+export type Navigation =
+	| { type: 'profile'; account: Account }
+	| { type: 'status'; status: Status }
+	| { type: 'tag'; name: string };
+
+export interface Page<T> {
+	items: T[];
+	next: string | undefined;
+	prev: string | undefined;
+}
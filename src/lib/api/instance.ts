@@ -56,3 +56,17 @@ export interface Marker {
 	home: MarkerData | undefined;
 	notifications: MarkerData | undefined;
 }
+
+export interface Capabilities {
+	// MIME types posts can be written in, always including text/plain
+	post_formats: string[];
+	// Visibilities posts can have, the standard four first
+	visibilities: string[];
+}
+
+export interface RateBudget {
+	limit: number | undefined;
+	remaining: number | undefined;
+	reset: string | undefined;
+	queued_writes: number;
+}
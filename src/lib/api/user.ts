@@ -1,5 +1,6 @@
 import type { Account } from './account';
-import type { Status } from './content';
+import type { Status, Visibility } from './content';
+import type { StatusContent } from './fetchers';
 
 export interface Relationship {
 	id: string;
@@ -51,3 +52,85 @@ export interface Notification {
 	target: Account | undefined;
 	type: NotificationType;
 }
+
+export interface QuietHours {
+	start_hour: number;
+	end_hour: number;
+}
+
+export interface NotifierSettings {
+	enabled: boolean;
+	mentions: boolean;
+	follows: boolean;
+	follow_requests: boolean;
+	favourites: boolean;
+	boosts: boolean;
+	quiet_hours: QuietHours | undefined;
+	max_per_minute: number;
+	poll_interval_secs: number;
+}
+
+export type CwBehaviour = 'collapse' | 'expand';
+
+export interface Settings {
+	default_visibility: Visibility;
+	cw_behaviour: CwBehaviour;
+	autoplay: boolean;
+	refresh_interval_secs: number;
+	default_language: string | undefined;
+	// Empty to read everything
+	read_languages: string[];
+}
+
+export type SettingsOverride = Partial<Settings>;
+
+export interface SettingsView {
+	effective: Settings;
+	global: Settings;
+	account: string | undefined;
+	overrides: SettingsOverride;
+}
+
+export type NotificationTarget = { type: 'status'; id: string } | { type: 'account'; id: string };
+
+export type OutboxAction =
+	| { type: 'post'; status: StatusContent; in_reply_to: string | undefined; idempotency_key: string }
+	| { type: 'favourite'; id: string }
+	| { type: 'boost'; id: string }
+	| { type: 'bookmark'; id: string }
+	| { type: 'unbookmark'; id: string };
+
+export type OutboxEntry = {
+	id: string;
+	action: OutboxAction;
+	account: string | undefined;
+	queued_at: string;
+	attempts: number;
+} & ({ state: 'pending' } | { state: 'sending' } | { state: 'failed'; error: string });
+
+export interface OutboxSent {
+	id: string;
+	status: Status;
+}
+
+export interface DraftPoll {
+	options: string[];
+	expires_in_secs: number;
+	multiple: boolean;
+}
+
+export interface DraftContent {
+	content: string;
+	cw: string | undefined;
+	visibility: Visibility | undefined;
+	in_reply_to: string | undefined;
+	quoting: string | undefined;
+	media_ids: string[];
+	poll: DraftPoll | undefined;
+}
+
+export interface Draft extends DraftContent {
+	id: string;
+	created_at: string;
+	updated_at: string;
+}
@@ -1,6 +1,5 @@
 import type { Writable } from 'svelte/store';
 import * as api from '$lib/api';
-import type { Filters } from './filtering';
 
 export interface TimelineContent {
 	type: 'timeline';
@@ -69,8 +68,10 @@ export type Accent =
 export interface SettingsContext {
 	theme: Writable<Theme>;
 	accent: Writable<Accent>;
-	filters: Writable<Filters>;
+	filters: Writable<api.FilterRule[]>;
 	font: Writable<string>;
+	// Owned by the backend, kept in sync through `settings-changed`
+	preferences: Writable<api.SettingsView | undefined>;
 }
 
 export const settingsContext = {};
@@ -0,0 +1,126 @@
+import * as api from '$lib/api';
+
+// Filters used to be JavaScript snippets kept in localStorage, evaluated in the webview
+interface LegacyFilter {
+	name: string;
+	applicationPredicate: string;
+	code: string;
+}
+
+export interface LegacyImport {
+	imported: string[];
+	skipped: string[];
+}
+
+// Rewrite a legacy snippet into the rule language. Only the helpers the old filters offered
+// are understood, anything else is left for the backend to reject.
+const translateExpression = (code: string): string => {
+	const source = code
+		.replace(/\bcwKeyword\(/g, 'cw(')
+		.replace(/\busernameContains\(/g, 'username(');
+
+	let out = '';
+	// Whether each open paren belongs to an anyOf, whose arguments become alternatives
+	const groups: boolean[] = [];
+	let quote: string | undefined = undefined;
+
+	for (let i = 0; i < source.length; i++) {
+		const c = source[i];
+
+		if (quote) {
+			if (c === '\\') {
+				out += c + (source[++i] ?? '');
+			} else if (c === quote) {
+				out += '"';
+				quote = undefined;
+			} else {
+				out += c === '"' ? '\\"' : c;
+			}
+			continue;
+		}
+
+		if (c === '"' || c === "'") {
+			quote = c;
+			out += '"';
+		} else if (source.startsWith('anyOf(', i)) {
+			groups.push(true);
+			out += '(';
+			i += 'anyOf('.length - 1;
+		} else if (c === '(') {
+			groups.push(false);
+			out += c;
+		} else if (c === ')') {
+			groups.pop();
+			out += c;
+		} else if (c === ',' && groups[groups.length - 1]) {
+			out += ' or ';
+		} else if (source.startsWith('&&', i)) {
+			out += ' and ';
+			i++;
+		} else if (source.startsWith('||', i)) {
+			out += ' or ';
+			i++;
+		} else if (c === '!') {
+			out += 'not ';
+		} else if (c !== ';') {
+			out += c;
+		}
+	}
+
+	return out.trim();
+};
+
+const translate = (filter: LegacyFilter): api.FilterRule => {
+	let code = filter.code.trim();
+	let outcome: api.FilterOutcome = 'hide';
+
+	const warning = /^warnIf\(([\s\S]*)\)$/.exec(code);
+	if (warning) {
+		code = warning[1];
+		outcome = 'warn';
+	}
+
+	let rule = translateExpression(code);
+	const predicate = filter.applicationPredicate.trim();
+	const enabled = predicate !== 'disabled()';
+	if (enabled && predicate !== 'true' && predicate !== '') {
+		rule = `(${translateExpression(predicate)}) and (${rule})`;
+	}
+
+	return { name: filter.name, enabled, rule, outcome };
+};
+
+// Move filters from the old localStorage format into the backend, once. Snippets that can't
+// be expressed as rules are kept aside in localStorage rather than thrown away.
+export const importLegacyFilters = async (
+	existing: api.FilterRule[]
+): Promise<[api.FilterRule[], LegacyImport] | undefined> => {
+	const stored = localStorage.getItem('filters');
+	if (!stored) {
+		return undefined;
+	}
+
+	let legacy: LegacyFilter[] = [];
+	try {
+		legacy = JSON.parse(stored);
+	} catch {
+		legacy = [];
+	}
+
+	let rules = existing;
+	const result: LegacyImport = { imported: [], skipped: [] };
+
+	// One at a time, so a rule the backend can't parse doesn't hold up the rest
+	for (const filter of legacy) {
+		try {
+			rules = await api.saveFilterRules([...rules, translate(filter)]);
+			result.imported.push(filter.name);
+		} catch {
+			result.skipped.push(filter.name);
+		}
+	}
+
+	localStorage.setItem('filters-legacy', stored);
+	localStorage.removeItem('filters');
+	return [rules, result];
+};
@@ -1,3 +1,5 @@
+import type { LoginMode } from '$lib/api';
+
 export enum InstanceType {
 	MASTODON = "Mastodon",
 	PLEROMA = "Pleroma",
@@ -9,5 +11,6 @@ export enum InstanceType {
 export interface BootstrapData {
 	instanceURL: string;
 	authURL: string;
-	instanceType: InstanceType
+	instanceType: InstanceType;
+	mode: LoginMode;
 }
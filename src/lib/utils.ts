@@ -25,7 +25,7 @@ export function elipsise(text: string, maxLen: number): string {
 	return text;
 }
 
-export function fullyQualifiedAccount(account: api.Account): string {
+export function fullyQualifiedAccount(account: Pick<api.Account, 'url' | 'username'>): string {
 	const domain = new URL(account.url);
 	return `@${account.username}@${domain.host}`;
 }
@@ -42,9 +42,10 @@ export async function openStatus(status: api.Status, content: Writable<MainConte
 }
 
 export function showError(content: Writable<MainContent>, error: unknown, context = "unknown context") {
-	logger.error('ctx', context, JSON.stringify(error))
+	const message = error instanceof Error ? error.message : JSON.stringify(error)
+	logger.error('ctx', context, message)
 	content.set({
 		type: 'error',
-		message: `ctx: ${context} - ${JSON.stringify(error)}` 
+		message: `ctx: ${context} - ${message}` 
 	})
 }